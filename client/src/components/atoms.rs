@@ -1,10 +1,19 @@
 pub mod avatar;
+pub mod benchmark;
 pub mod dark_mode;
 pub mod global_style;
 pub mod link;
 pub mod logo;
+pub mod name_form;
 pub mod overlay;
 pub mod this_peer_state;
+pub mod ice_config_state;
+pub mod connection_state;
+pub mod diagnostics;
 pub mod other_peers_state;
+pub mod room_form;
+pub mod room_state;
+pub mod visibility_controls;
+pub mod visibility_state;
 pub mod messages;
 mod animation;
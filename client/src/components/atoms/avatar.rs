@@ -1,44 +1,76 @@
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
 use gloo::{console::log, events::EventListener};
-use js_sys::{Array, ArrayBuffer, Uint8Array};
-use std::{
-    cell::{Cell, RefCell},
-    collections::VecDeque,
-    rc::Rc,
-};
+use js_sys::{Array, ArrayBuffer};
 
-use serde::{Deserialize, Serialize};
+use futures::channel::mpsc;
+use serde::Deserialize;
 use stylist::css;
 use tokio::sync::broadcast::Sender;
 use uuid::Uuid;
 use wasm_bindgen::{prelude::Closure, JsCast};
-use web_sys::{File, FileList, HtmlInputElement, RtcDataChannel};
-use yew::prelude::*;
+use web_sys::{File, FileList, HtmlInputElement};
+use yew::{platform::spawn_local, prelude::*};
+use yewdux::prelude::use_store;
 
-use crate::webrtc_manager::{WebRtcConnection, MAX_CHUNK_SIZE};
+use crate::{
+    transfer::{OutgoingTransfer, Reassembly, SendQueue},
+    webrtc_manager::WebRtcConnection,
+};
 
-use super::{messages::AppMessage, other_peers_state::WebRTCRole};
+use super::{
+    benchmark::Benchmark,
+    connection_state::{ConnectionStates, ConnectionStatus},
+    diagnostics::Diagnostics,
+    messages::{AppMessage, ClientMessage, RelayFrame, SignalingMessage},
+    other_peers_state::WebRTCRole,
+    visibility_state::Visibility,
+};
 
-#[derive(Serialize, Deserialize)]
-pub struct FileData {
-    pub name: String,
-    pub file_typ: String,
-    pub data: Vec<u8>,
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+pub enum DeviceClass {
+    Phone,
+    Tablet,
+    Desktop,
+}
+
+impl DeviceClass {
+    fn icon(&self) -> &'static str {
+        match self {
+            DeviceClass::Phone => "📱",
+            // no tablet glyph, a phone on its side reads as one
+            DeviceClass::Tablet => "📱",
+            DeviceClass::Desktop => "💻",
+        }
+    }
 }
 
 #[derive(Properties, Clone, Deserialize, Debug)]
 pub struct OtherPeer {
     pub id: Uuid,
+    pub device_id: Uuid,
     pub name: String,
     pub os: String,
+    pub browser: String,
+    pub device_class: DeviceClass,
+    pub model: Option<String>,
+    /// its browser can unpack compressed transfers
+    #[serde(default)]
+    pub decompress: bool,
     #[serde(skip_deserializing)]
     pub role: WebRTCRole,
+    /// a hidden peer that contacted this one, its first offer went out before we knew it
+    #[serde(skip_deserializing)]
+    pub revealed: bool,
     #[serde(skip_deserializing)]
     pub tx: Option<Sender<AppMessage>>,
+    #[serde(skip_deserializing)]
+    pub relay: Option<mpsc::Sender<RelayFrame>>,
 }
 
 impl PartialEq for OtherPeer {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.os == other.os && self.id == other.id
+        self.name == other.name && self.os == other.os && self.id == other.id && self.model == other.model
     }
 }
 
@@ -51,14 +83,21 @@ impl OtherPeer {
 #[function_component]
 pub fn Avatar(props: &OtherPeer) -> Html {
     log!("new peer joind with id:", props.id.clone().to_string());
+    // client hints and the user agent don't name the systems the same way
     let icon = match &props.os.to_lowercase()[..] {
         "windows" => "assets/windows.png",
-        "mac os x" => "assets/mac.png",
-        "linux" => "assets/linux.png",
+        "mac os x" | "macos" => "assets/mac.png",
+        "linux" | "ubuntu" | "debian" | "fedora" | "chrome os" | "chromeos" => "assets/linux.png",
         "android" => "assets/android.png",
-        "ios" => "assets/ios.png",
+        "ios" | "ipados" => "assets/ios.png",
         _ => "assets/unknown.png",
     };
+    // e.g. "Chrome on Pixel 7", the system stands in for unknown models
+    let device = format!(
+        "{} on {}",
+        props.browser,
+        props.model.as_deref().unwrap_or(&props.os)
+    );
 
     let stylesheet = css!(
         "
@@ -79,10 +118,24 @@ pub fn Avatar(props: &OtherPeer) -> Html {
             will-change: transform;
             align-items: center;
             justify-content: center;
+            position: relative;
         }
         img {
             width: 50px;
         }
+        device-class {
+            position: absolute;
+            right: -4px;
+            bottom: -4px;
+            font-size: 20px;
+        }
+        device-class.Tablet {
+            transform: rotate(90deg);
+        }
+        device {
+            opacity: 0.5;
+            white-space: nowrap;
+        }
         name {
             white-space: nowrap;
             overflow: hidden;
@@ -93,20 +146,212 @@ pub fn Avatar(props: &OtherPeer) -> Html {
             opacity: 0.5;
             transition: opacity 300ms;
         }
+        status {
+            display: flex;
+            align-items: center;
+            gap: 4px;
+        }
+        badge {
+            padding: 0 6px;
+            border-radius: 8px;
+            color: white;
+        }
+        .connecting {
+            background: #9E9E9E;
+        }
+        .direct {
+            background: #4CAF50;
+        }
+        .relayed {
+            background: #FF9800;
+        }
+        .failed {
+            background: #F44336;
+        }
+        button {
+            border: none;
+            background: none;
+            cursor: pointer;
+            text-decoration: underline;
+            color: inherit;
+        }
   
         "
     );
 
-    let webrtc_connection = use_state(|| {
-        log!("id of peer connecting to:", props.id.clone().to_string());
-        let webrtc_connection = WebRtcConnection::new();
-        webrtc_connection.init(
-            props.tx.clone().unwrap(),
-            props.id.clone(),
-            props.role.clone(),
+    let relayed = use_state(|| false);
+    // bumped every time the connection has to be torn down and rebuilt
+    let reconnects = use_state(|| 0);
+    // only the automatic rebuilds, a retry from the user doesn't use them up
+    let rebuilds = use_memo(|_| Rc::new(Cell::new(0)), ());
+    // survives the rebuilds so an interrupted transfer picks up where it stopped
+    let outgoing = use_mut_ref(SendQueue::default);
+    let incoming = use_mut_ref(Reassembly::default);
+
+    let (visibility, visibility_dispatch) = use_store::<Visibility>();
+    // while hidden nothing connects until one side sends a file, connecting
+    // right away would reveal this peer to everyone
+    let contacted = use_state(|| props.revealed);
+    {
+        let contacted = contacted.clone();
+        let was_hidden = use_mut_ref(|| visibility.hidden);
+        use_effect_with_deps(
+            move |hidden| {
+                // the other peers were told we left
+                if *hidden && !*was_hidden.borrow() {
+                    contacted.set(false);
+                }
+                *was_hidden.borrow_mut() = *hidden;
+            },
+            visibility.hidden,
         );
-        webrtc_connection
-    });
+    }
+    let active = !visibility.hidden || *contacted;
+    {
+        // our offer replaces the one the revealed peer sent before we listened
+        let tx = props.tx.clone().unwrap();
+        let other_peer = props.id;
+        use_effect_with_deps(
+            move |revealed| {
+                if *revealed {
+                    tx.send(AppMessage::CltMsg(ClientMessage::SignalingMessage(
+                        SignalingMessage::Reconnect(other_peer),
+                    )))
+                    .expect("error sending reconnect");
+                }
+            },
+            props.revealed,
+        );
+    }
+
+    let webrtc_connection = {
+        let relayed = relayed.clone();
+        let reconnects_handle = reconnects.clone();
+        let props = props.clone();
+        let (outgoing, incoming) = (outgoing.clone(), incoming.clone());
+        let rebuilds = (*rebuilds).clone();
+        use_memo(
+            move |(_, active)| {
+                if !active {
+                    // the other peer was told we left, it starts over when we come back
+                    outgoing.borrow_mut().clear();
+                    incoming.borrow_mut().clear();
+                    return None;
+                }
+                log!("id of peer connecting to:", props.id.clone().to_string());
+                let webrtc_connection = WebRtcConnection::new(outgoing, incoming);
+                webrtc_connection.init(
+                    props.tx.clone().unwrap(),
+                    props.id.clone(),
+                    props.role.clone(),
+                    props.relay.clone(),
+                    Callback::from(move |_| relayed.set(true)),
+                    Callback::from(move |_| reconnects_handle.set(*reconnects_handle + 1)),
+                    rebuilds,
+                );
+                Some(webrtc_connection)
+            },
+            (*reconnects, active),
+        )
+    };
+    {
+        let webrtc_connection = webrtc_connection.clone();
+        use_effect_with_deps(
+            move |_| {
+                move || {
+                    if let Some(webrtc_connection) = &*webrtc_connection {
+                        webrtc_connection.close()
+                    }
+                }
+            },
+            (*reconnects, active),
+        );
+    }
+
+    let (connection_states, connection_states_dispatch) = use_store::<ConnectionStates>();
+    let status = connection_states.get(&props.id);
+    {
+        let other_peer = props.id;
+        let connection_states_dispatch = connection_states_dispatch.clone();
+        let (outgoing, incoming) = (outgoing.clone(), incoming.clone());
+        use_effect_with_deps(
+            move |_| {
+                move || {
+                    // the peer left, the callbacks of its connections may keep these alive
+                    outgoing.borrow_mut().clear();
+                    incoming.borrow_mut().clear();
+                    connection_states_dispatch.reduce_mut(|states| {
+                        states.peers.remove(&other_peer);
+                        states.verified.remove(&other_peer);
+                        states.codes.remove(&other_peer);
+                    })
+                }
+            },
+            (),
+        );
+    }
+    // rebuilds the connection from scratch, trying a direct path again
+    let onretry = {
+        let relayed = relayed.clone();
+        let reconnects = reconnects.clone();
+        let tx = props.tx.clone().unwrap();
+        let other_peer = props.id;
+        Callback::from(move |_: MouseEvent| {
+            relayed.set(false);
+            tx.send(AppMessage::CltMsg(ClientMessage::SignalingMessage(
+                SignalingMessage::Reconnect(other_peer),
+            )))
+            .expect("error sending reconnect");
+            reconnects.set(*reconnects + 1);
+        })
+    };
+
+    // known once both peers revealed their nonces, compared out of band by the users
+    let sas = connection_states.codes.get(&props.id).cloned();
+    let verified = sas
+        .as_deref()
+        .is_some_and(|sas| connection_states.is_verified(&props.id, sas));
+    let show_sas = use_state(|| false);
+    let ontoggleverify = {
+        let show_sas = show_sas.clone();
+        Callback::from(move |_: MouseEvent| show_sas.set(!*show_sas))
+    };
+    let onconfirm = {
+        let show_sas = show_sas.clone();
+        let sas = sas.clone();
+        let other_peer = props.id;
+        Callback::from(move |_: MouseEvent| {
+            if let Some(sas) = sas.clone() {
+                connection_states_dispatch.reduce_mut(|states| {
+                    states.verified.insert(other_peer, sas);
+                });
+            }
+            show_sas.set(false);
+        })
+    };
+
+    let show_stats = use_state(|| false);
+    let ontogglestats = {
+        let show_stats = show_stats.clone();
+        Callback::from(move |_: MouseEvent| show_stats.set(!*show_stats))
+    };
+
+    // opt-in, compressing costs time that fast links don't win back
+    let compress = use_state(|| false);
+    let ontogglecompress = {
+        let compress = compress.clone();
+        Callback::from(move |_: MouseEvent| compress.set(!*compress))
+    };
+
+    let onblock = {
+        let tx = props.tx.clone().unwrap();
+        let other_peer = props.id;
+        Callback::from(move |_: MouseEvent| {
+            tx.send(AppMessage::CltMsg(ClientMessage::Block(other_peer)))
+                .expect("error sending block");
+            visibility_dispatch.reduce_mut(|visibility| visibility.blocked.push(other_peer));
+        })
+    };
 
     let ondragover = Callback::from(move |event: DragEvent| {
         event.prevent_default();
@@ -117,15 +362,21 @@ pub fn Avatar(props: &OtherPeer) -> Html {
 
     let ondrop = Callback::from(move |event: DragEvent| {
         event.prevent_default();
-        //let file = event.data_transfer().unwrap().files().unwrap();
     });
 
-    let data_channel = webrtc_connection.data_channel.clone();
+    let connection = (*webrtc_connection).clone();
+    // browsers without a `DecompressionStream` would fail the integrity check
+    let compress_files = *compress && props.decompress;
     let onchange = Callback::from(move |event: Event| {
         log!("on change");
         let input: HtmlInputElement = event.target_unchecked_into();
         let files = upload_files(input.files());
-        send_files(data_channel.clone(), files);
+        match connection.clone() {
+            // the connection sends through the relay once it fell back to it
+            Some(connection) => send_files(connection, files, compress_files),
+            // the files wait in the queue for the connection they bring up
+            None => queue_files(outgoing.clone(), contacted.clone(), files, compress_files),
+        }
     });
 
     html! {
@@ -133,14 +384,50 @@ pub fn Avatar(props: &OtherPeer) -> Html {
             <label for="input" {ondrop} {ondragenter} {ondragover}>
                <icon>
                     <img class="icon" src={icon} alt="avatar" />
+                    <device-class class={format!("{:?}", props.device_class)} title={format!("{:?}", props.device_class)}>{props.device_class.icon()}</device-class>
                 </icon>
-                <name class="smallfont">{&props.name}</name>
-                //todo! if *transfering {
-                    <p class="smallfont">{"Transfering"}</p>
-               // }
+                // same across reloads, tells apart devices that were given the same name
+                <name class="smallfont" title={format!("device {}", &props.device_id.simple().to_string()[..8])}>{&props.name}</name>
+                <device class="smallfont">{device}</device>
+                if *relayed {
+                    <p class="smallfont">{"Relayed through the server"}</p>
+                }
 
 
             </label>
+            <status class="smallfont">
+                if webrtc_connection.is_some() {
+                    <badge class={status.label()}>{status.label()}</badge>
+                }
+                if verified {
+                    <badge class="direct">{"verified"}</badge>
+                } else if sas.is_some() {
+                    <button class="smallfont" onclick={ontoggleverify}>{"verify"}</button>
+                }
+                if status == ConnectionStatus::Failed || *relayed {
+                    <button class="smallfont" onclick={onretry}>{"retry"}</button>
+                }
+                if props.decompress {
+                    <button class="smallfont" onclick={ontogglecompress}>
+                        {if *compress { "compression on" } else { "compression off" }}
+                    </button>
+                }
+                if webrtc_connection.is_some() {
+                    <button class="smallfont" onclick={ontogglestats}>{"stats"}</button>
+                }
+                <button class="smallfont" onclick={onblock}>{"block"}</button>
+            </status>
+            if let (true, false, Some(sas)) = (*show_sas, verified, &sas) {
+                <verification class="smallfont column center">
+                    <span>{"Check that the other device shows the same code:"}</span>
+                    <strong>{sas}</strong>
+                    <button class="smallfont" onclick={onconfirm}>{"codes match"}</button>
+                </verification>
+            }
+            if let (true, Some(connection)) = (*show_stats, &*webrtc_connection) {
+                <Diagnostics peer_connection={connection.peer_connection.clone()} />
+                <Benchmark connection={connection.clone()} />
+            }
                <input {onchange} id="input" type="file" multiple=true/>
 
         </avatar>
@@ -154,28 +441,60 @@ fn upload_files(files: Option<FileList>) -> Vec<File> {
             .unwrap()
             .unwrap()
             .map(|v| web_sys::File::from(v.unwrap()));
-        //.map(File::from);
         uploaded_files.extend(files);
     }
     log!("file unloaded", uploaded_files.len());
     uploaded_files
 }
 
-fn send_files(data_channel: RtcDataChannel, files: Vec<File>) {
+fn send_files(webrtc_connection: WebRtcConnection, files: Vec<File>, compress: bool) {
+    for file in files {
+        let webrtc_connection = webrtc_connection.clone();
+        read_as_array_buffer_then(file, move |file, array_buffer| {
+            let webrtc_connection = webrtc_connection.clone();
+            let (name, file_typ) = (file.name(), file.type_());
+            spawn_local(async move {
+                let transfer = OutgoingTransfer::prepare(name, file_typ, array_buffer, compress).await;
+                webrtc_connection.send(transfer);
+            });
+        })
+    }
+}
+
+/// queues the files before there is a connection, which is brought up once they are
+fn queue_files(
+    outgoing: Rc<RefCell<SendQueue>>,
+    contacted: UseStateHandle<bool>,
+    files: Vec<File>,
+    compress: bool,
+) {
     for file in files {
-        read_as_array_buffer_then_send(data_channel.clone(), file)
+        let outgoing = outgoing.clone();
+        let contacted = contacted.clone();
+        read_as_array_buffer_then(file, move |file, array_buffer| {
+            let outgoing = outgoing.clone();
+            let contacted = contacted.clone();
+            let (name, file_typ) = (file.name(), file.type_());
+            spawn_local(async move {
+                let transfer = OutgoingTransfer::prepare(name, file_typ, array_buffer, compress).await;
+                outgoing.borrow_mut().push_file(transfer);
+                contacted.set(true);
+            });
+        })
     }
 }
-fn read_as_array_buffer_then_send(data_channel: RtcDataChannel, file: File) {
+
+fn read_as_array_buffer_then(file: File, mut on_load: impl FnMut(&File, ArrayBuffer) + 'static) {
     log!("sending file");
     log!(format!("{}", file.size()));
 
     let file_reader = web_sys::FileReader::new().unwrap();
     let file_reader_clone = file_reader.clone();
+    let file_clone = file.clone();
     let onloadend_cb = Closure::<dyn FnMut(_)>::new(move |_e: web_sys::ProgressEvent| {
-        let file = file_reader_clone.result().unwrap();
-        let array_buffer: ArrayBuffer = file.unchecked_into();
-        send_as_array_buffer(data_channel.clone(), array_buffer);
+        let result = file_reader_clone.result().unwrap();
+        let array_buffer: ArrayBuffer = result.unchecked_into();
+        on_load(&file_clone, array_buffer);
     });
 
     file_reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
@@ -184,86 +503,3 @@ fn read_as_array_buffer_then_send(data_channel: RtcDataChannel, file: File) {
         .expect("blob not readable");
     onloadend_cb.forget();
 }
-
-fn send_as_array_buffer(data_channel: RtcDataChannel, array_buffer: ArrayBuffer) {
-    /*
-    let data_channel = webrtc_connection.data_channel.clone();
-    {
-        let data_channel_ = data_channel.clone();
-        let queue = queue.clone();
-        let bytes_sent = bytes_sent.clone();
-        let onbuff_amount_low = Closure::wrap(Box::new(move |_| {
-            send_till_buffer_full(data_channel_.clone(), queue.clone(), bytes_sent.clone());
-        }) as Box<dyn FnMut(Event)>);
-            data_channel.set_onbufferedamountlow(Some(onbuff_amount_low.as_ref().unchecked_ref()));
-            onbuff_amount_low.forget();
-        }
-        */
-    let bytes_sent = Rc::new(Cell::new(0));
-    let queue = Rc::new(RefCell::new(VecDeque::new()));
-    set_onbufferedamountlow(data_channel.clone(), queue.clone(), bytes_sent.clone());
-
-    let array_length = array_buffer.byte_length();
-    let numb_of_slices = (array_length / MAX_CHUNK_SIZE) + 1;
-    let mut begin = 0;
-    for _ in 0..numb_of_slices {
-        let chunk = array_buffer.slice_with_end(begin, begin + MAX_CHUNK_SIZE);
-        log!(chunk.byte_length());
-        queue.borrow_mut().push_back(chunk);
-        send_till_buffer_full(data_channel.clone(), queue.clone(), bytes_sent.clone());
-        begin += MAX_CHUNK_SIZE;
-    }
-}
-
-fn set_onbufferedamountlow(
-    data_channel: RtcDataChannel,
-    queue: Rc<RefCell<VecDeque<ArrayBuffer>>>,
-    bytes_sent: Rc<Cell<u32>>,
-) {
-    let data_channel_ = data_channel.clone();
-    let onbuff_amount_low_cb = Closure::wrap(Box::new(move |_| {
-        send_till_buffer_full(data_channel_.clone(), queue.clone(), bytes_sent.clone());
-    }) as Box<dyn FnMut(Event)>);
-    data_channel.set_onbufferedamountlow(Some(onbuff_amount_low_cb.as_ref().unchecked_ref()));
-    onbuff_amount_low_cb.forget();
-}
-
-pub fn send_till_buffer_full(
-    data_channel: RtcDataChannel,
-    queue: Rc<RefCell<VecDeque<ArrayBuffer>>>,
-    bytes_sent: Rc<Cell<u32>>,
-) {
-    loop {
-        let chunk = queue.borrow_mut().pop_front();
-        if let Some(chunk) = chunk {
-            let res = data_channel.send_with_array_buffer(&chunk);
-            if res.is_err() {
-                queue.borrow_mut().push_front(chunk);
-                break;
-            }
-            bytes_sent.set(bytes_sent.get() + MAX_CHUNK_SIZE);
-            log!("bytes sent: ", bytes_sent.get());
-        } else {
-            break;
-        }
-    }
-}
-
-/*  //let len = queue.borrow().len();
-for _ in  0..queue.borrow().len() {
-    log!("buff ammount", data_channel.buffered_amount());
-    //if data_channel.buffered_amount() < data_channel.buffered_amount_low_threshold() {
-       // log!("buff is less");
-       // if err {continue;}
-//slicing
-//let chunks = array.slice(begin);
-
-//for chunk in chunks {
-//}
-
-let array = Array::of1(&arraybuff);
-
-log!(array.length()); // length = 0
-let file = File::new_with_buffer_source_sequence(&array, &name).unwrap();
-log!(file.size()); // size = 0
-log!(file.type_()); */
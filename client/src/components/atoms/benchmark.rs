@@ -0,0 +1,96 @@
+use stylist::css;
+use yew::{platform::spawn_local, prelude::*};
+
+use crate::{
+    pacing::Pacing,
+    webrtc_manager::{WebRtcConnection, DATA_CHANNELS},
+};
+
+use super::diagnostics::format_bytes;
+
+/// filler sent for every setting
+const BENCHMARK_BYTES: u32 = 32 * 1024 * 1024;
+
+const SETTINGS: [(&str, Pacing, usize); 6] = [
+    (
+        "16 KiB chunks, 1 MiB buffered",
+        Pacing::Fixed { chunk_size: 16 * 1024, high_watermark: 1024 * 1024 },
+        1,
+    ),
+    (
+        "64 KiB chunks, 4 MiB buffered",
+        Pacing::Fixed { chunk_size: 64 * 1024, high_watermark: 4 * 1024 * 1024 },
+        1,
+    ),
+    (
+        "256 KiB chunks, 8 MiB buffered",
+        Pacing::Fixed { chunk_size: 256 * 1024, high_watermark: 8 * 1024 * 1024 },
+        1,
+    ),
+    ("adaptive, single channel", Pacing::Adaptive, 1),
+    (
+        "64 KiB chunks, 4 MiB buffered, all channels",
+        Pacing::Fixed { chunk_size: 64 * 1024, high_watermark: 4 * 1024 * 1024 },
+        DATA_CHANNELS,
+    ),
+    ("adaptive, all channels", Pacing::Adaptive, DATA_CHANNELS),
+];
+
+#[derive(Properties, PartialEq)]
+pub struct BenchmarkProps {
+    pub connection: WebRtcConnection,
+}
+
+/// compares the throughput of chunk size, watermark and striping settings toward one peer
+#[function_component]
+pub fn Benchmark(props: &BenchmarkProps) -> Html {
+    let stylesheet = css!(
+        "
+        display: flex;
+        flex-direction: column;
+        align-items: flex-start;
+        margin-top: 4px;
+        "
+    );
+
+    let running = use_state(|| false);
+    let results = use_state(Vec::<(&'static str, Result<f64, &'static str>)>::new);
+    let onrun = {
+        let running = running.clone();
+        let results = results.clone();
+        let connection = props.connection.clone();
+        Callback::from(move |_: MouseEvent| {
+            running.set(true);
+            let running = running.clone();
+            let results = results.clone();
+            let connection = connection.clone();
+            spawn_local(async move {
+                let mut measured = vec![];
+                for (name, pacing, channels) in SETTINGS {
+                    let throughput = connection.benchmark(pacing, channels, BENCHMARK_BYTES).await;
+                    measured.push((name, throughput));
+                    results.set(measured.clone());
+                }
+                running.set(false);
+            });
+        })
+    };
+
+    html! {
+        <benchmark class={classes!("smallfont", stylesheet)}>
+            { for results.iter().map(|(name, throughput)| html! {
+                <span>{format!(
+                    "{}: {}",
+                    name,
+                    match throughput {
+                        Ok(throughput) => format!("{}/s", format_bytes(*throughput)),
+                        Err(reason) => reason.to_string(),
+                    }
+                )}</span>
+            }) }
+            <button class="smallfont" onclick={onrun} disabled={*running}>
+                {if *running { "Benchmarking…" } else { "Run benchmark" }}
+            </button>
+        </benchmark>
+    }
+}
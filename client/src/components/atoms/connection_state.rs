@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+use yewdux::store::Store;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionStatus {
+    #[default]
+    Connecting,
+    /// the selected candidate pair doesn't go through a TURN server
+    Direct,
+    /// the data goes through a TURN server or the signaling server
+    Relayed,
+    Failed,
+}
+
+impl ConnectionStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionStatus::Connecting => "connecting",
+            ConnectionStatus::Direct => "direct",
+            ConnectionStatus::Relayed => "relayed",
+            ConnectionStatus::Failed => "failed",
+        }
+    }
+}
+
+/// state of the peer connection toward every other peer
+#[derive(Default, Clone, PartialEq, Store)]
+pub struct ConnectionStates {
+    pub peers: HashMap<Uuid, ConnectionStatus>,
+    /// short authentication string the user confirmed for each peer, a rebuilt
+    /// connection comes with new fingerprints and has to be verified again
+    pub verified: HashMap<Uuid, String>,
+    /// short authentication string of the current connection to each peer,
+    /// once both peers revealed their nonces
+    pub codes: HashMap<Uuid, String>,
+}
+
+impl ConnectionStates {
+    pub fn get(&self, peer: &Uuid) -> ConnectionStatus {
+        self.peers.get(peer).copied().unwrap_or_default()
+    }
+
+    pub fn is_verified(&self, peer: &Uuid, sas: &str) -> bool {
+        self.verified.get(peer).map(String::as_str) == Some(sas)
+    }
+}
@@ -0,0 +1,136 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gloo::{
+    file::{Blob, ObjectUrl},
+    timers::callback::Interval,
+    utils::document,
+};
+use js_sys::Map;
+use stylist::css;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlElement, RtcPeerConnection};
+use yew::{platform::spawn_local, prelude::*};
+
+use crate::stats::{self, Candidate, ConnectionStats};
+
+const POLL_INTERVAL_MS: u32 = 1000;
+
+#[derive(Properties, PartialEq)]
+pub struct DiagnosticsProps {
+    pub peer_connection: RtcPeerConnection,
+}
+
+/// numbers of the connection toward one peer, for debugging slow transfers
+#[function_component]
+pub fn Diagnostics(props: &DiagnosticsProps) -> Html {
+    let stylesheet = css!(
+        "
+        display: flex;
+        flex-direction: column;
+        align-items: flex-start;
+        padding: 8px;
+        border-radius: 8px;
+        background: rgba(0, 0, 0, 0.05);
+
+        button {
+            align-self: center;
+            margin-top: 4px;
+        }
+        "
+    );
+
+    let stats = use_state(|| None::<ConnectionStats>);
+    // last raw report, exported along the summary
+    let report = use_mut_ref(|| None::<Map>);
+    {
+        let stats = stats.clone();
+        let report = report.clone();
+        use_effect_with_deps(
+            move |peer_connection: &RtcPeerConnection| {
+                let peer_connection = peer_connection.clone();
+                let previous = Rc::new(RefCell::new(None::<ConnectionStats>));
+                let interval = Interval::new(POLL_INTERVAL_MS, move || {
+                    let peer_connection = peer_connection.clone();
+                    let previous = previous.clone();
+                    let stats = stats.clone();
+                    let report = report.clone();
+                    spawn_local(async move {
+                        let Some(new_report) = stats::get_stats(&peer_connection).await else {
+                            return;
+                        };
+                        let new_stats =
+                            ConnectionStats::from_report(&new_report, previous.borrow().as_ref());
+                        *previous.borrow_mut() = Some(new_stats.clone());
+                        *report.borrow_mut() = Some(new_report);
+                        stats.set(Some(new_stats));
+                    });
+                });
+                move || drop(interval)
+            },
+            props.peer_connection.clone(),
+        );
+    }
+
+    // kept until the next export so the browser has time to start the download
+    let export_url = use_mut_ref(|| None::<ObjectUrl>);
+    let onexport = {
+        let stats = stats.clone();
+        Callback::from(move |_: MouseEvent| {
+            let export = serde_json::json!({
+                "summary": *stats,
+                "report": report.borrow().as_ref().map(stats::report_to_json),
+            });
+            let blob = Blob::new_with_options(export.to_string().as_str(), Some("application/json"));
+            let url = ObjectUrl::from(blob);
+            let link: HtmlElement = document()
+                .create_element("a")
+                .expect("error creating export link")
+                .unchecked_into();
+            link.set_attribute("href", &url).expect("error setting export href");
+            link.set_attribute("download", "connection-stats.json")
+                .expect("error setting export file name");
+            link.click();
+            *export_url.borrow_mut() = Some(url);
+        })
+    };
+
+    let Some(stats) = (*stats).clone() else {
+        return html! {
+            <diagnostics class={classes!("smallfont", stylesheet)}>{"Collecting stats…"}</diagnostics>
+        };
+    };
+    html! {
+        <diagnostics class={classes!("smallfont", stylesheet)}>
+            <span>{format!("RTT: {}", stats.rtt_ms.map_or("-".to_owned(), |rtt| format!("{:.0} ms", rtt)))}</span>
+            <span>{format!("Sent: {} ({}/s)", format_bytes(stats.bytes_sent), format_bytes(stats.send_rate))}</span>
+            <span>{format!("Received: {} ({}/s)", format_bytes(stats.bytes_received), format_bytes(stats.receive_rate))}</span>
+            <span>{format!("Local: {}", format_candidate(&stats.local_candidate))}</span>
+            <span>{format!("Remote: {}", format_candidate(&stats.remote_candidate))}</span>
+            <button class="smallfont" onclick={onexport}>{"Export JSON"}</button>
+        </diagnostics>
+    }
+}
+
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_candidate(candidate: &Option<Candidate>) -> String {
+    match candidate {
+        Some(candidate) => format!(
+            "{} {} {}:{}",
+            candidate.candidate_type,
+            candidate.protocol.as_deref().unwrap_or("-"),
+            candidate.address.as_deref().unwrap_or("-"),
+            candidate.port.map_or("-".to_owned(), |port| port.to_string()),
+        ),
+        None => "-".to_owned(),
+    }
+}
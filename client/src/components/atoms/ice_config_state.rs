@@ -0,0 +1,8 @@
+use yewdux::store::Store;
+
+use crate::webrtc_manager::IceServer;
+
+#[derive(Clone, Default, PartialEq, Store, Debug)]
+pub struct IceConfig {
+    pub ice_servers: Vec<IceServer>,
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::webrtc_manager::IceCandidate;
+use crate::webrtc_manager::{IceCandidate, IceServer};
 
 use super::{avatar::OtherPeer, this_peer_state::ThisPeer};
 
@@ -10,6 +10,29 @@ pub enum SignalingMessage {
     Offer(Uuid, String),
     Answer(Uuid, String),
     IceCandidate(Uuid, IceCandidate),
+    Reconnect(Uuid),
+}
+
+/// why the server refused to move this peer to a named room
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+pub enum RoomError {
+    InvalidName,
+    PassphraseRequired,
+    WrongPassphrase,
+    RoomFull,
+    TooManyAttempts,
+}
+
+impl RoomError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            RoomError::InvalidName => "Room names can't be empty or longer than 64 characters",
+            RoomError::PassphraseRequired => "This room is protected by a passphrase",
+            RoomError::WrongPassphrase => "Wrong passphrase",
+            RoomError::RoomFull => "This room is full",
+            RoomError::TooManyAttempts => "Too many wrong passphrases, wait a moment before trying again",
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Debug)]
@@ -17,20 +40,63 @@ pub enum ClientMessage {
     CheckOnline,
     Disconnect,
     SignalingMessage(SignalingMessage),
+    JoinRoom {
+        room: String,
+        passphrase: Option<String>,
+    },
+    SetHidden(bool),
+    Rename(String),
+    Block(Uuid),
+    Unblock(Uuid),
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub enum ServerMessage {
     PeerJoined(OtherPeer),
+    /// a hidden peer contacting this one
+    PeerRevealed(OtherPeer),
     ConnectedPeers(Vec<OtherPeer>),
     PeerLeft(Uuid),
+    /// a peer got a new display name
+    PeerUpdated(OtherPeer),
+    RoomJoined(String),
+    RoomError(RoomError),
     PeerData(ThisPeer),
+    IceConfig(Vec<IceServer>),
     CheckOnline,
     SignalingMessage(SignalingMessage),
+    /// the server couldn't handle the last message, a bug on this side
+    Error { code: String, message: String },
+}
+
+/// file data tunnelled through the signaling server when the peers can't
+/// connect directly, sent as a binary frame: 16 bytes of peer id then the payload
+#[derive(Clone, Debug)]
+pub struct RelayFrame {
+    pub peer: Uuid,
+    pub data: Vec<u8>,
+}
+
+impl RelayFrame {
+    pub fn decode(mut frame: Vec<u8>) -> Option<Self> {
+        if frame.len() < 16 {
+            return None;
+        }
+        let data = frame.split_off(16);
+        let peer = Uuid::from_slice(&frame).ok()?;
+        Some(RelayFrame { peer, data })
+    }
+
+    pub fn encode(self) -> Vec<u8> {
+        let mut frame = self.peer.as_bytes().to_vec();
+        frame.extend(self.data);
+        frame
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum AppMessage {
     SrvrMsg(ServerMessage),
     CltMsg(ClientMessage),
+    Relay(RelayFrame),
 }
@@ -0,0 +1,59 @@
+use stylist::css;
+use tokio::sync::broadcast::Sender;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yewdux::prelude::use_store;
+
+use super::{
+    messages::{AppMessage, ClientMessage},
+    this_peer_state::{Device, ThisPeer},
+};
+
+#[derive(Properties)]
+pub struct NameFormProps {
+    pub tx: Sender<AppMessage>,
+}
+
+impl PartialEq for NameFormProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
+/// picks the name the other peers see, kept for the next visits
+#[function_component]
+pub fn NameForm(props: &NameFormProps) -> Html {
+    let stylesheet = css!(
+        "
+        display: flex;
+        justify-content: center;
+        gap: 4px;
+        padding: 0 8px 8px;
+        "
+    );
+
+    let (this_peer, _) = use_store::<ThisPeer>();
+    let (_, device_dispatch) = use_store::<Device>();
+    let name_ref = use_node_ref();
+    let onsubmit = {
+        let tx = props.tx.clone();
+        let name_ref = name_ref.clone();
+        Callback::from(move |event: SubmitEvent| {
+            event.prevent_default();
+            let input = name_ref.cast::<HtmlInputElement>().expect("no name input");
+            let name = input.value().trim().to_owned();
+            input.set_value("");
+            tx.send(AppMessage::CltMsg(ClientMessage::Rename(name.clone())))
+                .expect("error sending rename");
+            // an empty name goes back to a generated one
+            device_dispatch.reduce_mut(|device| device.name = (!name.is_empty()).then_some(name));
+        })
+    };
+
+    html! {
+        <form class={classes!("smallfont", stylesheet)} {onsubmit}>
+            <input ref={name_ref} type="text" maxlength="32" placeholder={this_peer.name.clone()} />
+            <button type="submit">{"Rename"}</button>
+        </form>
+    }
+}
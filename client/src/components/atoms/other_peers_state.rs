@@ -1,13 +1,27 @@
+use uuid::Uuid;
 use yew::Reducible;
+use yewdux::prelude::Dispatch;
 
+use super::{avatar::OtherPeer, messages::ServerMessage, this_peer_state::ThisPeer};
 
-use super::{avatar::OtherPeer, messages::ServerMessage};
-
+/// perfect negotiation role, the polite peer gives way when both sides offer at once
 #[derive(Default, Clone, Debug, PartialEq)]
 pub enum WebRTCRole {
     #[default]
-    Server,
-    Client,
+    Polite,
+    Impolite,
+}
+
+impl WebRTCRole {
+    /// both sides compare the same ids, so they agree on the roles however
+    /// they learned about each other, hidden peers revealing themselves included
+    pub fn between(this_peer: Uuid, other_peer: Uuid) -> Self {
+        if this_peer < other_peer {
+            WebRTCRole::Polite
+        } else {
+            WebRTCRole::Impolite
+        }
+    }
 }
 
 #[derive(Default, Clone, PartialEq)]
@@ -15,16 +29,40 @@ pub struct OtherPeers {
     pub peers: Vec<OtherPeer>,
 }
 
+impl OtherPeers {
+    /// a peer shown again after hiding may be known already
+    fn with_peer(&self, mut peer: OtherPeer, this_peer: Uuid, revealed: bool) -> Self {
+        let mut peers = self.peers.clone();
+        if !peers.iter().any(|known| known.id == peer.id) {
+            peer.set_role(WebRTCRole::between(this_peer, peer.id));
+            peer.revealed = revealed;
+            peers.push(peer);
+        }
+        Self { peers }
+    }
+}
+
 impl Reducible for OtherPeers {
     type Action = ServerMessage;
 
     fn reduce(self: std::rc::Rc<Self>, action: Self::Action) -> std::rc::Rc<Self> {
+        // the server sends this peer's data before anything about the others
+        let this_peer = Dispatch::<ThisPeer>::new().get().id;
         match action {
-            ServerMessage::ConnectedPeers(peers) => { Self { peers } }.into(),
-            ServerMessage::PeerJoined(mut peer) => {
-                peer.set_role(WebRTCRole::Client);
+            ServerMessage::ConnectedPeers(mut peers) => {
+                for peer in peers.iter_mut() {
+                    peer.set_role(WebRTCRole::between(this_peer, peer.id));
+                }
+                Self { peers }
+            }
+            .into(),
+            ServerMessage::PeerJoined(peer) => self.with_peer(peer, this_peer, false).into(),
+            ServerMessage::PeerRevealed(peer) => self.with_peer(peer, this_peer, true).into(),
+            ServerMessage::PeerUpdated(updated) => {
                 let mut peers = self.peers.clone();
-                peers.push(peer);
+                if let Some(peer) = peers.iter_mut().find(|peer| peer.id == updated.id) {
+                    peer.name = updated.name;
+                }
                 Self { peers }
             }
             .into(),
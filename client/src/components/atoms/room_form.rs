@@ -0,0 +1,75 @@
+use stylist::css;
+use tokio::sync::broadcast::Sender;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yewdux::prelude::use_store;
+
+use super::{
+    messages::{AppMessage, ClientMessage},
+    room_state::RoomState,
+};
+
+#[derive(Properties)]
+pub struct RoomFormProps {
+    pub tx: Sender<AppMessage>,
+}
+
+impl PartialEq for RoomFormProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
+/// joins or creates a named room, a passphrase given when creating it protects it
+#[function_component]
+pub fn RoomForm(props: &RoomFormProps) -> Html {
+    let stylesheet = css!(
+        "
+        display: flex;
+        flex-flow: row wrap;
+        justify-content: center;
+        align-items: center;
+        gap: 4px;
+        padding: 8px;
+
+        error {
+            width: 100%;
+            text-align: center;
+            color: #F44336;
+        }
+        "
+    );
+
+    let (room, _) = use_store::<RoomState>();
+    let name_ref = use_node_ref();
+    let passphrase_ref = use_node_ref();
+    let onsubmit = {
+        let tx = props.tx.clone();
+        let name_ref = name_ref.clone();
+        let passphrase_ref = passphrase_ref.clone();
+        Callback::from(move |event: SubmitEvent| {
+            event.prevent_default();
+            let name = name_ref.cast::<HtmlInputElement>().expect("no room name input");
+            let passphrase = passphrase_ref
+                .cast::<HtmlInputElement>()
+                .expect("no passphrase input")
+                .value();
+            tx.send(AppMessage::CltMsg(ClientMessage::JoinRoom {
+                room: name.value(),
+                passphrase: (!passphrase.is_empty()).then_some(passphrase),
+            }))
+            .expect("error sending join room");
+        })
+    };
+
+    html! {
+        <form class={classes!("smallfont", stylesheet)} {onsubmit}>
+            <input ref={name_ref} type="text" placeholder="Room name" />
+            <input ref={passphrase_ref} type="password" placeholder="Passphrase (optional)" />
+            <button type="submit">{"Join room"}</button>
+            if let Some(error) = &room.error {
+                <error>{error.message()}</error>
+            }
+        </form>
+    }
+}
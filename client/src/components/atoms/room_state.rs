@@ -0,0 +1,10 @@
+use yewdux::store::Store;
+
+use super::messages::RoomError;
+
+/// the named room this peer is in, `None` while it only sees its network
+#[derive(Clone, Default, PartialEq, Store, Debug)]
+pub struct RoomState {
+    pub name: Option<String>,
+    pub error: Option<RoomError>,
+}
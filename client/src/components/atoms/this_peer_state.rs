@@ -10,3 +10,21 @@ pub struct ThisPeer {
     pub id: Uuid,
   
 }
+
+/// what identifies this browser across connections, kept in local storage
+#[derive(Clone, Serialize, Deserialize, PartialEq, Store, Debug)]
+#[store(storage = "local")]
+pub struct Device {
+    pub id: Uuid,
+    /// picked by the user, the server generates one when it's missing
+    pub name: Option<String>,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device {
+            id: Uuid::new_v4(),
+            name: None,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use stylist::css;
+use tokio::sync::broadcast::Sender;
+use yew::prelude::*;
+use yewdux::prelude::use_store;
+
+use super::{
+    messages::{AppMessage, ClientMessage},
+    visibility_state::Visibility,
+};
+
+#[derive(Properties)]
+pub struct VisibilityControlsProps {
+    pub tx: Sender<AppMessage>,
+}
+
+impl PartialEq for VisibilityControlsProps {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
+/// hides this peer from everyone it doesn't send files to, and lifts the blocks
+#[function_component]
+pub fn VisibilityControls(props: &VisibilityControlsProps) -> Html {
+    let stylesheet = css!(
+        "
+        display: flex;
+        justify-content: center;
+        gap: 4px;
+        "
+    );
+
+    let (visibility, dispatch) = use_store::<Visibility>();
+    let ontogglehidden = {
+        let tx = props.tx.clone();
+        let hidden = !visibility.hidden;
+        let dispatch = dispatch.clone();
+        Callback::from(move |_: MouseEvent| {
+            tx.send(AppMessage::CltMsg(ClientMessage::SetHidden(hidden)))
+                .expect("error sending set hidden");
+            dispatch.reduce_mut(|visibility| visibility.hidden = hidden);
+        })
+    };
+    let onunblock = {
+        let tx = props.tx.clone();
+        let blocked = visibility.blocked.clone();
+        Callback::from(move |_: MouseEvent| {
+            for id in &blocked {
+                tx.send(AppMessage::CltMsg(ClientMessage::Unblock(*id)))
+                    .expect("error sending unblock");
+            }
+            dispatch.reduce_mut(|visibility| visibility.blocked.clear());
+        })
+    };
+
+    html! {
+        <visibility class={classes!("smallfont", stylesheet)}>
+            <button class="smallfont" onclick={ontogglehidden}>
+                {if visibility.hidden { "Show me" } else { "Hide me" }}
+            </button>
+            if !visibility.blocked.is_empty() {
+                <button class="smallfont" onclick={onunblock}>
+                    {format!("Unblock {} peers", visibility.blocked.len())}
+                </button>
+            }
+        </visibility>
+    }
+}
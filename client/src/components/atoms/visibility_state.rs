@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use yewdux::store::Store;
+
+/// kept for the tab so a reload doesn't show a hidden peer
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq, Store, Debug)]
+#[store(storage = "session")]
+pub struct Visibility {
+    pub hidden: bool,
+    /// ids change with every connection, so the block list doesn't outlive it
+    #[serde(skip)]
+    pub blocked: Vec<Uuid>,
+}
+
+impl Visibility {
+    pub fn is_blocked(&self, id: &Uuid) -> bool {
+        self.blocked.contains(id)
+    }
+}
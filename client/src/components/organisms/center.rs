@@ -1,10 +1,11 @@
+use futures::channel::mpsc;
 use stylist::css;
 use tokio::sync::broadcast::{channel, Sender};
 use yew::prelude::*;
 use yewdux::prelude::use_store;
 
 use crate::{
-    components::atoms::{avatar::Avatar, other_peers_state::OtherPeers, this_peer_state::ThisPeer, messages::AppMessage},
+    components::atoms::{avatar::Avatar, other_peers_state::OtherPeers, name_form::NameForm, room_form::RoomForm, this_peer_state::ThisPeer, messages::{AppMessage, RelayFrame}, visibility_controls::VisibilityControls, visibility_state::Visibility},
     connection_manager::connection_manager,
 };
 
@@ -49,14 +50,17 @@ pub fn Center() -> Html {
     );
 
     let tx = &*use_state(|| channel::<AppMessage>(100).0);
+    let relay = use_state(|| None);
     let (_, this_peer_dispatch) = use_store::<ThisPeer>();
     let other_peers = use_reducer_eq(|| OtherPeers::default());
+    let (visibility, _) = use_store::<Visibility>();
     {
         let tx = tx.clone();
+        let relay = relay.clone();
         let other_peers = other_peers.dispatcher();
         use_effect_with_deps(
             move |()| {
-                connection_manager(other_peers, this_peer_dispatch, tx);
+                relay.set(Some(connection_manager(other_peers, this_peer_dispatch, tx)));
             },
             (),
         );
@@ -64,7 +68,10 @@ pub fn Center() -> Html {
 
     html! {
     <center class={stylesheet}>
-        if other_peers.peers.is_empty() {
+        <RoomForm tx={tx.clone()} />
+        <NameForm tx={tx.clone()} />
+        <VisibilityControls tx={tx.clone()} />
+        if other_peers.peers.iter().all(|peer| visibility.is_blocked(&peer.id)) {
             <no-peers>
                 <h3>{"Open Shrut on other devices to send files"}</h3>
             </no-peers>
@@ -74,20 +81,26 @@ pub fn Center() -> Html {
                 {"Tap or click to send a file"}
             </instruction>
             <peers class="center">
-                {display_peers(other_peers.clone(), tx.clone())}
+                {display_peers(other_peers.clone(), &visibility, tx.clone(), (*relay).clone())}
             </peers>
         }
     </center>
     }
 }
 
-fn display_peers(other_peers: UseReducerHandle<OtherPeers>, tx: Sender<AppMessage>) -> Vec<Html> {
+fn display_peers(
+    other_peers: UseReducerHandle<OtherPeers>,
+    visibility: &Visibility,
+    tx: Sender<AppMessage>,
+    relay: Option<mpsc::Sender<RelayFrame>>,
+) -> Vec<Html> {
     other_peers
         .peers
         .iter()
+        .filter(|peer| !visibility.is_blocked(&peer.id))
         .map(|peer| {
             html! {
-                <Avatar key={peer.id.clone().to_string()} id={peer.id.clone()} name={peer.name.clone()} os={peer.os.clone()} role={peer.role.clone()} tx={tx.clone()} />
+                <Avatar key={peer.id.clone().to_string()} id={peer.id.clone()} device_id={peer.device_id} name={peer.name.clone()} os={peer.os.clone()} browser={peer.browser.clone()} device_class={peer.device_class} model={peer.model.clone()} decompress={peer.decompress} role={peer.role.clone()} revealed={peer.revealed} tx={tx.clone()} relay={relay.clone()} />
             }
         })
         .collect()
@@ -6,7 +6,9 @@ use crate::components::atoms::{
     dark_mode::{Mode, ModeState},
     link::Link,
     logo::Logo,
+    room_state::RoomState,
     this_peer_state::ThisPeer,
+    visibility_state::Visibility,
 };
 
 #[function_component(Footer)]
@@ -14,6 +16,14 @@ pub fn footer() -> Html {
     let (store, _) = use_store::<ThisPeer>();
     let current_peer_name = store.name.clone();
 
+    let (room, _) = use_store::<RoomState>();
+    let (visibility, _) = use_store::<Visibility>();
+    let discoverable_by = match &room.name {
+        _ if visibility.hidden => "You are hidden, only the peers you send files to can see you".to_owned(),
+        Some(name) => format!("You can be discovered by everyone in the room {}", name),
+        None => "You can be discovered by everyone on this network".to_owned(),
+    };
+
     let (store, _) = use_store::<ModeState>();
     let text_color = match store.mode {
         Mode::Dark => "white",
@@ -63,7 +73,7 @@ pub fn footer() -> Html {
         <footer class ={classes!("column",{stylesheet})}>
                 <Logo />
                 <display_name> {"You are known as"} <span>{current_peer_name}</span></display_name>
-                <note class="smallfont">{"You can be discovered by everyone on this network"}</note>
+                <note class="smallfont">{discoverable_by}</note>
 
                 <div class="right">
                     <Link icon={github_icon} target="https://github.com/AbdesamedBendjeddou/Shrut/" alt="Github" />
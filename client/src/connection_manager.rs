@@ -1,9 +1,15 @@
 use crate::components::atoms::{
-    messages::{AppMessage, ClientMessage, ServerMessage},
+    ice_config_state::IceConfig,
+    messages::{AppMessage, ClientMessage, RelayFrame, ServerMessage},
     other_peers_state::OtherPeers,
-    this_peer_state::ThisPeer,
+    room_state::RoomState,
+    this_peer_state::{Device, ThisPeer},
+    visibility_state::Visibility,
 };
+use crate::transfer;
 use futures::{
+    channel::mpsc,
+    future::{select, Either},
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
@@ -13,21 +19,85 @@ use gloo::{
     net::websocket::{futures::WebSocket, Message},
     utils::window,
 };
+use js_sys::{Array, Function, Promise, Reflect};
 use tokio::sync::broadcast::Sender;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
 use yew::{platform::spawn_local, UseReducerDispatcher};
 use yewdux::prelude::Dispatch;
 use AppMessage::*;
 
+/// number of relayed frames waiting for the websocket before file reading is paused
+const RELAY_BUFFER: usize = 8;
+
+/// returns the sender used to tunnel file data through the server
 pub fn connection_manager(
     other_peers: UseReducerDispatcher<OtherPeers>,
     this_peer: Dispatch<ThisPeer>,
     tx: Sender<AppMessage>,
-) {
-    let ws = WebSocket::open("ws://127.0.0.1:5050/ws").unwrap();
-    let (sender, receiver) = futures::StreamExt::split(ws);
-    dispatcher(receiver, tx.clone(), other_peers, this_peer);
-    send(sender, tx.clone());
+) -> mpsc::Sender<RelayFrame> {
+    let (relay, frames) = mpsc::channel(RELAY_BUFFER);
+    {
+        let tx = tx.clone();
+        spawn_local(async move {
+            let ws = WebSocket::open(&socket_url().await).unwrap();
+            let (sender, receiver) = futures::StreamExt::split(ws);
+            dispatcher(receiver, tx.clone(), other_peers, this_peer);
+            send(sender, tx, frames);
+        });
+    }
     create_event_send_disconnect_onclose(tx.clone());
+    relay
+}
+
+/// on the server the page came from, carries what it can't know about this browser
+async fn socket_url() -> String {
+    let hidden = Dispatch::<Visibility>::new().get().hidden;
+    let device = Dispatch::<Device>::new().get();
+    let location = window().location();
+    // a page served over https can't open a plain websocket
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws",
+    };
+    let mut url = format!(
+        "{}://{}/ws?hidden={}&device={}&decompress={}",
+        scheme,
+        location.host().expect("page without a host"),
+        hidden,
+        device.id,
+        transfer::can_decompress()
+    );
+    if let Some(name) = &device.name {
+        url.push_str(&format!("&name={}", js_sys::encode_uri_component(name)));
+    }
+    if let Some(model) = device_model().await {
+        url.push_str(&format!("&model={}", js_sys::encode_uri_component(&model)));
+    }
+    url
+}
+
+/// a high entropy client hint, chromium only tells it when asked and other browsers not at all
+async fn device_model() -> Option<String> {
+    let navigator = Reflect::get(&window(), &"navigator".into()).ok()?;
+    let user_agent_data = Reflect::get(&navigator, &"userAgentData".into())
+        .ok()
+        .filter(|user_agent_data| !user_agent_data.is_undefined())?;
+    let get_high_entropy_values: Function =
+        Reflect::get(&user_agent_data, &"getHighEntropyValues".into())
+            .ok()?
+            .dyn_into()
+            .ok()?;
+    let values: Promise = get_high_entropy_values
+        .call1(&user_agent_data, &Array::of1(&"model".into()))
+        .ok()?
+        .dyn_into()
+        .ok()?;
+    let values = JsFuture::from(values).await.ok()?;
+    Reflect::get(&values, &"model".into())
+        .ok()?
+        .as_string()
+        .filter(|model| !model.is_empty())
 }
 
 fn dispatcher(
@@ -37,7 +107,16 @@ fn dispatcher(
     this_peer: Dispatch<ThisPeer>,
 ) {
     spawn_local(async move {
-        while let Some(Ok(Message::Text(msg))) = receiver.next().await {
+        while let Some(Ok(msg)) = receiver.next().await {
+            let msg = match msg {
+                Message::Text(msg) => msg,
+                Message::Bytes(frame) => {
+                    if let Some(frame) = RelayFrame::decode(frame) {
+                        tx.send(Relay(frame)).expect("error sender");
+                    }
+                    continue;
+                }
+            };
             log!("from recv".to_owned() + &msg);
             let msg = serde_json::from_str::<ServerMessage>(&msg)
                 .expect("dispatcher: uncorrect msg format"); // make loop continue if error
@@ -45,6 +124,16 @@ fn dispatcher(
                 ServerMessage::PeerData(this_peer_data) => {
                     this_peer.reduce(|_| this_peer_data.into())
                 }
+                ServerMessage::IceConfig(ice_servers) => {
+                    Dispatch::<IceConfig>::new().set(IceConfig { ice_servers })
+                }
+                ServerMessage::RoomJoined(name) => Dispatch::<RoomState>::new().set(RoomState {
+                    name: Some(name),
+                    error: None,
+                }),
+                ServerMessage::RoomError(error) => {
+                    Dispatch::<RoomState>::new().reduce_mut(|room| room.error = Some(error))
+                }
                 ServerMessage::CheckOnline => {
                     tx.send(CltMsg(ClientMessage::CheckOnline))
                         .expect("error sender");
@@ -52,24 +141,37 @@ fn dispatcher(
                 ServerMessage::SignalingMessage(_) => {
                     tx.send(SrvrMsg(msg)).expect("error sender");
                 }
+                ServerMessage::Error { code, message } => {
+                    log!("server error", code, message)
+                }
                 _ => other_peers.dispatch(msg),
             }
         }
     });
 }
 
-fn send(mut sender: SplitSink<WebSocket, Message>, tx: Sender<AppMessage>) {
+fn send(
+    mut sender: SplitSink<WebSocket, Message>,
+    tx: Sender<AppMessage>,
+    mut frames: mpsc::Receiver<RelayFrame>,
+) {
     let mut rx = tx.subscribe();
     spawn_local(async move {
-        while let Ok(msg) = rx.recv().await {
-            if let CltMsg(msg) = msg {
-                let msg = serde_json::to_string(&msg).unwrap();
-                log!(&("from sender".to_owned() + &msg.clone()));
-                sender
-                    .send(Message::Text(msg))
-                    .await
-                    .expect("error sending to server") // make loop continue
-            }
+        loop {
+            let msg = match select(Box::pin(rx.recv()), frames.next()).await {
+                Either::Left((Ok(CltMsg(msg)), _)) => {
+                    let msg = serde_json::to_string(&msg).unwrap();
+                    log!(&("from sender".to_owned() + &msg.clone()));
+                    Message::Text(msg)
+                }
+                Either::Left((Ok(_), _)) => continue,
+                Either::Right((Some(frame), _)) => Message::Bytes(frame.encode()),
+                Either::Left((Err(_), _)) | Either::Right((None, _)) => break,
+            };
+            sender
+                .send(msg)
+                .await
+                .expect("error sending to server") // make loop continue
         }
     });
 }
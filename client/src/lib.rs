@@ -1,5 +1,9 @@
 mod components;
 mod connection_manager;
+mod pacing;
+mod stats;
+mod transfer;
+mod verification;
 mod webrtc_manager;
 
 use yew::prelude::*;
@@ -0,0 +1,135 @@
+use std::cell::Cell;
+
+use js_sys::{Date, Reflect};
+use wasm_bindgen::JsValue;
+use web_sys::{RtcDataChannel, RtcPeerConnection};
+
+/// chunk size before the SCTP transport is known, every browser accepts it
+pub const DEFAULT_CHUNK_SIZE: u32 = 16384;
+/// every relayed frame counts against the server's `--message-rate`, so they are
+/// made as large as its default `--max-message-size` allows
+pub const RELAY_CHUNK_SIZE: u32 = 48 * 1024;
+/// the biggest message chromium and firefox agree on
+const MAX_CHUNK_SIZE: u32 = 256 * 1024;
+/// chromium closes a data channel whose buffer grows past 16 MiB
+const MAX_HIGH_WATERMARK: u32 = 16 * 1024 * 1024 - MAX_CHUNK_SIZE;
+/// how much sending time the adaptive watermark keeps buffered,
+/// so the channel doesn't run dry before `bufferedamountlow` refills it
+const BUFFERED_SECONDS: f64 = 0.25;
+
+/// how a connection slices and buffers outgoing data
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pacing {
+    /// largest chunk the other peer accepts, watermark following the measured throughput
+    Adaptive,
+    Fixed { chunk_size: u32, high_watermark: u32 },
+}
+
+/// chunk size and buffering watermark of one data channel
+#[derive(Debug)]
+pub struct Pacer {
+    mode: Cell<Pacing>,
+    /// `RTCSctpTransport.maxMessageSize` of the negotiated connection
+    max_message_size: Cell<u32>,
+    chunk_size: Cell<u32>,
+    high_watermark: Cell<u32>,
+    /// bytes handed to the data channel so far
+    sent: Cell<f64>,
+    /// time and bytes drained out of the buffer at the last `bufferedamountlow`
+    last_sample: Cell<Option<(f64, f64)>>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Pacer {
+            mode: Cell::new(Pacing::Adaptive),
+            max_message_size: Cell::new(DEFAULT_CHUNK_SIZE),
+            chunk_size: Cell::new(DEFAULT_CHUNK_SIZE),
+            high_watermark: Cell::new(DEFAULT_CHUNK_SIZE * 64),
+            sent: Cell::new(0.0),
+            last_sample: Cell::new(None),
+        }
+    }
+}
+
+impl Pacer {
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size.get()
+    }
+
+    pub fn high_watermark(&self) -> u32 {
+        self.high_watermark.get()
+    }
+
+    pub fn mode(&self) -> Pacing {
+        self.mode.get()
+    }
+
+    /// reads the max message size once the SCTP transport is up, i.e. when the data channel opens
+    pub fn configure(&self, peer_connection: &RtcPeerConnection, data_channels: &[RtcDataChannel]) {
+        let max_message_size = Reflect::get(peer_connection, &"sctp".into())
+            .ok()
+            .filter(|sctp| !sctp.is_null() && !sctp.is_undefined())
+            .and_then(|sctp| Reflect::get(&sctp, &"maxMessageSize".into()).ok())
+            .as_ref()
+            .and_then(JsValue::as_f64)
+            // 0 means no limit, infinity is reported by some browsers
+            .map(|size| if size == 0.0 { f64::INFINITY } else { size })
+            .unwrap_or(DEFAULT_CHUNK_SIZE as f64);
+        self.max_message_size
+            .set(max_message_size.min(MAX_CHUNK_SIZE as f64) as u32);
+        self.set_mode(self.mode.get(), data_channels);
+    }
+
+    pub fn set_mode(&self, mode: Pacing, data_channels: &[RtcDataChannel]) {
+        self.mode.set(mode);
+        self.last_sample.set(None);
+        let (chunk_size, high_watermark) = match mode {
+            Pacing::Adaptive => {
+                let chunk_size = self.max_message_size.get();
+                (chunk_size, chunk_size * 64)
+            }
+            Pacing::Fixed { chunk_size, high_watermark } => {
+                (chunk_size.min(self.max_message_size.get()), high_watermark)
+            }
+        };
+        self.chunk_size.set(chunk_size);
+        self.set_high_watermark(high_watermark, data_channels);
+    }
+
+    pub fn on_sent(&self, bytes: u32) {
+        self.sent.set(self.sent.get() + bytes as f64);
+    }
+
+    /// measures the throughput between two `bufferedamountlow` events and buffers
+    /// enough data to keep sending for `BUFFERED_SECONDS`, split across the data channels
+    pub fn on_buffered_amount_low(&self, data_channels: &[RtcDataChannel]) {
+        if self.mode.get() != Pacing::Adaptive {
+            return;
+        }
+        let now = Date::now();
+        let buffered: f64 = data_channels
+            .iter()
+            .map(|data_channel| data_channel.buffered_amount() as f64)
+            .sum();
+        let drained = self.sent.get() - buffered;
+        if let Some((last_time, last_drained)) = self.last_sample.get() {
+            let elapsed = (now - last_time) / 1000.0;
+            if elapsed > 0.0 {
+                let throughput = (drained - last_drained) / elapsed;
+                let high_watermark = throughput * BUFFERED_SECONDS / data_channels.len() as f64;
+                self.set_high_watermark(high_watermark as u32, data_channels);
+            }
+        }
+        self.last_sample.set(Some((now, drained)));
+    }
+
+    /// the watermark applies to each data channel
+    fn set_high_watermark(&self, high_watermark: u32, data_channels: &[RtcDataChannel]) {
+        let high_watermark = high_watermark.clamp(self.chunk_size.get() * 4, MAX_HIGH_WATERMARK);
+        self.high_watermark.set(high_watermark);
+        for data_channel in data_channels {
+            data_channel.set_buffered_amount_low_threshold(high_watermark / 4);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use js_sys::{Array, Map, Reflect, JSON};
+use serde::Serialize;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::RtcPeerConnection;
+
+/// the figures of the diagnostics panel, read from one `getStats` report
+#[derive(Serialize, Clone, Default, PartialEq, Debug)]
+pub struct ConnectionStats {
+    /// milliseconds, as reported by the browser
+    pub timestamp: f64,
+    pub rtt_ms: Option<f64>,
+    pub bytes_sent: f64,
+    pub bytes_received: f64,
+    /// bytes per second since the previous report
+    pub send_rate: f64,
+    pub receive_rate: f64,
+    pub local_candidate: Option<Candidate>,
+    pub remote_candidate: Option<Candidate>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Candidate {
+    /// host, srflx, prflx or relay
+    pub candidate_type: String,
+    pub protocol: Option<String>,
+    pub address: Option<String>,
+    pub port: Option<f64>,
+}
+
+impl ConnectionStats {
+    pub fn from_report(report: &Map, previous: Option<&ConnectionStats>) -> Self {
+        let Some(pair) = selected_candidate_pair(report) else {
+            return ConnectionStats::default();
+        };
+        let mut stats = ConnectionStats {
+            timestamp: number(&pair, "timestamp").unwrap_or_default(),
+            rtt_ms: number(&pair, "currentRoundTripTime").map(|rtt| rtt * 1000.0),
+            bytes_sent: number(&pair, "bytesSent").unwrap_or_default(),
+            bytes_received: number(&pair, "bytesReceived").unwrap_or_default(),
+            send_rate: 0.0,
+            receive_rate: 0.0,
+            local_candidate: candidate(report, &pair, "localCandidateId"),
+            remote_candidate: candidate(report, &pair, "remoteCandidateId"),
+        };
+        if let Some(previous) = previous {
+            let elapsed = (stats.timestamp - previous.timestamp) / 1000.0;
+            if elapsed > 0.0 {
+                stats.send_rate = (stats.bytes_sent - previous.bytes_sent).max(0.0) / elapsed;
+                stats.receive_rate = (stats.bytes_received - previous.bytes_received).max(0.0) / elapsed;
+            }
+        }
+        stats
+    }
+
+    /// whether either end of the selected pair goes through a TURN server
+    pub fn is_relayed(&self) -> bool {
+        [&self.local_candidate, &self.remote_candidate]
+            .iter()
+            .any(|candidate| matches!(candidate, Some(candidate) if candidate.candidate_type == "relay"))
+    }
+}
+
+pub async fn get_stats(peer_connection: &RtcPeerConnection) -> Option<Map> {
+    let report = JsFuture::from(peer_connection.get_stats()).await.ok()?;
+    Some(report.unchecked_into())
+}
+
+/// the candidate pair ICE is currently sending over
+pub fn selected_candidate_pair(report: &Map) -> Option<JsValue> {
+    let mut selected_pair = None;
+    report.for_each(&mut |stats, id| {
+        // chrome points at the pair from the transport, firefox flags the pair itself
+        if string(&stats, "type").as_deref() == Some("transport") {
+            selected_pair = string(&stats, "selectedCandidatePairId").map(JsValue::from);
+        } else if selected_pair.is_none()
+            && string(&stats, "type").as_deref() == Some("candidate-pair")
+            && Reflect::get(&stats, &"selected".into()).ok() == Some(JsValue::TRUE)
+        {
+            selected_pair = Some(id);
+        }
+    });
+    let pair = report.get(&selected_pair?);
+    (!pair.is_undefined()).then_some(pair)
+}
+
+/// the whole report as a JSON array, for attaching to bug reports
+pub fn report_to_json(report: &Map) -> serde_json::Value {
+    let entries = Array::from(&report.values());
+    JSON::stringify(&entries)
+        .ok()
+        .and_then(|json| json.as_string())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn candidate(report: &Map, pair: &JsValue, field: &str) -> Option<Candidate> {
+    let candidate = report.get(&string(pair, field)?.into());
+    Some(Candidate {
+        candidate_type: string(&candidate, "candidateType")?,
+        protocol: string(&candidate, "protocol"),
+        // older browsers call it ip
+        address: string(&candidate, "address").or_else(|| string(&candidate, "ip")),
+        port: number(&candidate, "port"),
+    })
+}
+
+fn string(stats: &JsValue, field: &str) -> Option<String> {
+    Reflect::get(stats, &field.into()).ok()?.as_string()
+}
+
+fn number(stats: &JsValue, field: &str) -> Option<f64> {
+    Reflect::get(stats, &field.into()).ok()?.as_f64()
+}
@@ -0,0 +1,538 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use gloo::{
+    console::{error, log},
+    file::Blob,
+};
+use js_sys::{Array, ArrayBuffer, Date, Function, Math, Reflect, Uint8Array};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{ReadableStream, Response};
+
+const HEADER: u8 = 0;
+const CHUNK: u8 = 1;
+const PROGRESS: u8 = 2;
+const RECEIVED: u8 = 3;
+const COMMITMENT: u8 = 4;
+const NONCE: u8 = 5;
+const FILLER: u8 = 6;
+/// tag, transfer id and byte offset in front of every chunk
+pub const CHUNK_HEADER_LEN: u32 = 9;
+/// how long a file may go without a frame before what arrived of it is dropped,
+/// long enough for the connection to be rebuilt and the transfer resumed
+const STALE_AFTER_MS: f64 = 10.0 * 60.0 * 1000.0;
+
+/// mime types whose content is compressed already
+const COMPRESSED_TYPES: [&str; 14] = [
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+    "application/pdf",
+    "application/epub+zip",
+    "application/java-archive",
+    "application/vnd.android.package-archive",
+    "font/woff2",
+];
+/// prefixes of mime types that are compressed already, office documents are zip files
+const COMPRESSED_PREFIXES: [&str; 5] = [
+    "image/",
+    "video/",
+    "audio/",
+    "application/vnd.openxmlformats-officedocument.",
+    "application/vnd.oasis.opendocument.",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Deflate,
+}
+
+impl Compression {
+    /// name of the format for `CompressionStream`
+    fn format(&self) -> &'static str {
+        match self {
+            Compression::Deflate => "deflate",
+        }
+    }
+}
+
+/// announces a file before its chunks, which may overtake it on another data channel
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferHeader {
+    pub id: u32,
+    pub name: String,
+    pub file_typ: String,
+    /// bytes going over the wire, compressed ones when `compression` is set
+    pub size: u64,
+    pub compression: Option<Compression>,
+    /// sha-256 of the uncompressed file
+    pub digest: Vec<u8>,
+}
+
+/// a file hashed and, when asked for, compressed, ready to be queued
+pub struct OutgoingTransfer {
+    name: String,
+    file_typ: String,
+    compression: Option<Compression>,
+    digest: Vec<u8>,
+    data: ArrayBuffer,
+}
+
+impl OutgoingTransfer {
+    /// compression is skipped for formats that are compressed already
+    /// and when it doesn't make the file smaller
+    pub async fn prepare(name: String, file_typ: String, data: ArrayBuffer, compress: bool) -> Self {
+        let digest = Sha256::digest(Uint8Array::new(&data).to_vec()).to_vec();
+        let mut transfer = OutgoingTransfer {
+            name,
+            file_typ,
+            compression: None,
+            digest,
+            data,
+        };
+        if !compress || !is_compressible(&transfer.file_typ) {
+            return transfer;
+        }
+        match transform(&transfer.data, Compression::Deflate, "CompressionStream").await {
+            Ok(compressed) if compressed.byte_length() < transfer.data.byte_length() => {
+                log!(format!(
+                    "{} compressed from {} to {} bytes",
+                    transfer.name,
+                    transfer.data.byte_length(),
+                    compressed.byte_length()
+                ));
+                transfer.compression = Some(Compression::Deflate);
+                transfer.data = compressed;
+            }
+            Ok(_) => log!(transfer.name.clone(), "doesn't compress, sending it as is"),
+            Err(err) => error!(format!("failed to compress {}: {:?}", transfer.name, err)),
+        }
+        transfer
+    }
+}
+
+/// told to the other peers, which only compress what this browser can unpack
+pub fn can_decompress() -> bool {
+    Reflect::has(&js_sys::global(), &"DecompressionStream".into()).unwrap_or_default()
+}
+
+fn is_compressible(file_typ: &str) -> bool {
+    let file_typ = file_typ.to_lowercase();
+    // text based or raw images
+    if file_typ == "image/svg+xml" || file_typ == "image/bmp" {
+        return true;
+    }
+    !COMPRESSED_TYPES.contains(&file_typ.as_str())
+        && !COMPRESSED_PREFIXES
+            .iter()
+            .any(|prefix| file_typ.starts_with(prefix))
+}
+
+/// runs the data through the browser's `CompressionStream` or `DecompressionStream`
+async fn transform(data: &ArrayBuffer, compression: Compression, stream: &str) -> Result<ArrayBuffer, JsValue> {
+    let constructor: Function = Reflect::get(&js_sys::global(), &stream.into())?.dyn_into()?;
+    let transform = Reflect::construct(&constructor, &Array::of1(&compression.format().into()))?;
+    let readable = web_sys::Blob::new_with_buffer_source_sequence(&Array::of1(data))?.stream();
+    let pipe_through: Function = Reflect::get(&readable, &"pipeThrough".into())?.dyn_into()?;
+    let transformed: ReadableStream = pipe_through.call1(&readable, &transform)?.dyn_into()?;
+    // a response reads the whole stream back into a single buffer
+    let response = Response::new_with_opt_readable_stream(Some(&transformed))?;
+    JsFuture::from(response.array_buffer()?).await?.dyn_into()
+}
+
+/// what a receiver already has, sent on every new connection and when falling back
+/// to the relay so the sender picks up from there instead of where it was
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Progress {
+    /// transfers received whole, even if the sender never heard about it
+    complete: Vec<u32>,
+    /// transfer ids and how many bytes of each arrived without a gap
+    partial: Vec<(u32, u32)>,
+}
+
+/// what goes over the data channels and the websocket relay
+#[derive(Debug)]
+pub enum Frame {
+    Header(TransferHeader),
+    /// chunks are keyed by where they start, the chunk size changes with the path
+    Chunk { transfer: u32, offset: u32, data: Vec<u8> },
+    Progress(Progress),
+    /// the receiver got the whole transfer or gave up on it, the sender can let go of it
+    Received(u32),
+    /// hash of the nonce the short authentication string is derived from
+    Commitment(Vec<u8>),
+    /// sent once the other peer's commitment arrived
+    Nonce(Vec<u8>),
+    /// benchmark data, dropped by the receiver as it arrives
+    Filler,
+}
+
+impl Frame {
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        match *bytes.first()? {
+            HEADER => rmp_serde::from_slice(&bytes[1..]).ok().map(Frame::Header),
+            CHUNK if bytes.len() >= CHUNK_HEADER_LEN as usize => Some(Frame::Chunk {
+                transfer: u32::from_be_bytes(bytes[1..5].try_into().ok()?),
+                offset: u32::from_be_bytes(bytes[5..9].try_into().ok()?),
+                data: bytes[CHUNK_HEADER_LEN as usize..].to_vec(),
+            }),
+            PROGRESS => rmp_serde::from_slice(&bytes[1..]).ok().map(Frame::Progress),
+            RECEIVED => Some(Frame::Received(u32::from_be_bytes(bytes.get(1..5)?.try_into().ok()?))),
+            COMMITMENT => Some(Frame::Commitment(bytes[1..].to_vec())),
+            NONCE => Some(Frame::Nonce(bytes[1..].to_vec())),
+            FILLER => Some(Frame::Filler),
+            _ => None,
+        }
+    }
+
+    fn encode_header(header: &TransferHeader) -> ArrayBuffer {
+        let mut bytes = vec![HEADER];
+        bytes.extend(rmp_serde::to_vec(header).expect("error encoding transfer header"));
+        Uint8Array::from(&bytes[..]).buffer()
+    }
+
+    pub fn encode_progress(progress: &Progress) -> ArrayBuffer {
+        let mut bytes = vec![PROGRESS];
+        bytes.extend(rmp_serde::to_vec(progress).expect("error encoding transfer progress"));
+        Uint8Array::from(&bytes[..]).buffer()
+    }
+
+    pub fn encode_received(transfer: u32) -> ArrayBuffer {
+        let mut bytes = vec![RECEIVED];
+        bytes.extend(transfer.to_be_bytes());
+        Uint8Array::from(&bytes[..]).buffer()
+    }
+
+    pub fn encode_commitment(commitment: &[u8]) -> ArrayBuffer {
+        Self::encode_bytes(COMMITMENT, commitment)
+    }
+
+    pub fn encode_nonce(nonce: &[u8]) -> ArrayBuffer {
+        Self::encode_bytes(NONCE, nonce)
+    }
+
+    fn encode_bytes(tag: u8, data: &[u8]) -> ArrayBuffer {
+        let mut bytes = vec![tag];
+        bytes.extend(data);
+        Uint8Array::from(&bytes[..]).buffer()
+    }
+
+    /// `len` bytes of zeros behind the tag
+    fn encode_filler(len: u32) -> ArrayBuffer {
+        let frame = Uint8Array::new_with_length(1 + len);
+        frame.set_index(0, FILLER);
+        frame.buffer()
+    }
+
+    /// copies `len` bytes of `data` from `offset` behind the chunk header
+    fn encode_chunk(transfer: u32, data: &ArrayBuffer, offset: u32, len: u32) -> ArrayBuffer {
+        let frame = Uint8Array::new_with_length(CHUNK_HEADER_LEN + len);
+        let mut header = vec![CHUNK];
+        header.extend(transfer.to_be_bytes());
+        header.extend(offset.to_be_bytes());
+        frame.set(&Uint8Array::from(&header[..]), 0);
+        frame.set(
+            &Uint8Array::new_with_byte_offset_and_length(data, offset, len),
+            CHUNK_HEADER_LEN,
+        );
+        frame.buffer()
+    }
+}
+
+/// a file being sliced into chunks as the data channels free up, kept until
+/// the receiver confirms it so a broken connection can send the rest again
+struct OutgoingFile {
+    header: TransferHeader,
+    data: ArrayBuffer,
+    header_sent: bool,
+    /// the next byte to send
+    offset: u32,
+}
+
+impl OutgoingFile {
+    fn is_sent(&self) -> bool {
+        self.header_sent && self.offset >= self.data.byte_length()
+    }
+}
+
+/// files waiting to be sent to one peer, shared by the connections that replace
+/// each other so an interrupted transfer carries on
+#[derive(Default)]
+pub struct SendQueue {
+    /// progress and receipts, they go out before any file data
+    control: VecDeque<ArrayBuffer>,
+    files: VecDeque<OutgoingFile>,
+    /// set on a new connection or path until the other peer said what it already has
+    paused: bool,
+    /// benchmark bytes left to send, after the files
+    filler: u32,
+}
+
+impl SendQueue {
+    pub fn push_file(&mut self, transfer: OutgoingTransfer) {
+        // random so transfers relayed through the server don't clash with direct ones
+        let id = (Math::random() * u32::MAX as f64) as u32;
+        let header = TransferHeader {
+            id,
+            name: transfer.name,
+            file_typ: transfer.file_typ,
+            size: transfer.data.byte_length() as u64,
+            compression: transfer.compression,
+            digest: transfer.digest,
+        };
+        self.files.push_back(OutgoingFile {
+            header,
+            data: transfer.data,
+            header_sent: false,
+            offset: 0,
+        });
+    }
+
+    /// measures the path without going through the receiver's reassembly
+    pub fn push_filler(&mut self, bytes: u32) {
+        self.filler += bytes;
+    }
+
+    /// files not confirmed by the receiver yet
+    pub fn has_files(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    pub fn push_control(&mut self, frame: ArrayBuffer) {
+        self.control.push_back(frame);
+    }
+
+    /// holds back the files until `resume`, the chunks in flight on the old path may be lost.
+    /// what was queued for the old path is dropped, the progress sent next covers the receipts
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.control.clear();
+        self.filler = 0;
+    }
+
+    /// rewinds every file to what the receiver has, the ones it doesn't know start over
+    pub fn resume(&mut self, progress: &Progress) {
+        self.paused = false;
+        self.files.retain(|file| !progress.complete.contains(&file.header.id));
+        for file in self.files.iter_mut() {
+            let received = progress
+                .partial
+                .iter()
+                .find(|(id, _)| *id == file.header.id)
+                .map(|(_, received)| *received);
+            file.header_sent = received.is_some();
+            file.offset = received.unwrap_or_default().min(file.data.byte_length());
+        }
+    }
+
+    /// drops everything, the peer is gone
+    pub fn clear(&mut self) {
+        *self = SendQueue::default();
+    }
+
+    pub fn confirm(&mut self, transfer: u32) {
+        self.files.retain(|file| file.header.id != transfer);
+    }
+
+    /// nothing left to send, the files may still wait for their receipts
+    pub fn is_empty(&self) -> bool {
+        self.control.is_empty()
+            && (self.paused || self.filler == 0 && self.files.iter().all(OutgoingFile::is_sent))
+    }
+
+    /// next frame no bigger than `max_frame_size`
+    pub fn next_frame(&mut self, max_frame_size: u32) -> Option<ArrayBuffer> {
+        if let Some(frame) = self.control.pop_front() {
+            return Some(frame);
+        }
+        if self.paused {
+            return None;
+        }
+        let Some(file) = self.files.iter_mut().find(|file| !file.is_sent()) else {
+            if self.filler == 0 {
+                return None;
+            }
+            let len = (max_frame_size - 1).min(self.filler);
+            self.filler -= len;
+            return Some(Frame::encode_filler(len));
+        };
+        if !file.header_sent {
+            file.header_sent = true;
+            return Some(Frame::encode_header(&file.header));
+        }
+        let len = (max_frame_size - CHUNK_HEADER_LEN).min(file.data.byte_length() - file.offset);
+        let frame = Frame::encode_chunk(file.header.id, &file.data, file.offset, len);
+        file.offset += len;
+        Some(frame)
+    }
+}
+
+struct IncomingFile {
+    header: Option<TransferHeader>,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    received: u64,
+    /// when the last frame of it arrived, in ms since the epoch
+    updated: f64,
+}
+
+impl Default for IncomingFile {
+    fn default() -> Self {
+        IncomingFile {
+            header: None,
+            chunks: BTreeMap::new(),
+            received: 0,
+            updated: Date::now(),
+        }
+    }
+}
+
+/// a file that left the reassembly, either way the sender is told it can let go of it
+pub enum Reassembled {
+    Complete(ReceivedFile),
+    /// the chunks didn't fit the announced size
+    Aborted { id: u32, name: String },
+}
+
+/// every chunk of a file, still to be decompressed and checked
+pub struct ReceivedFile {
+    pub header: TransferHeader,
+    data: Vec<u8>,
+}
+
+impl ReceivedFile {
+    pub async fn verify(self) -> Result<Blob, String> {
+        let data = match self.header.compression {
+            Some(compression) => {
+                let compressed = Uint8Array::from(&self.data[..]).buffer();
+                let data = transform(&compressed, compression, "DecompressionStream")
+                    .await
+                    .map_err(|err| format!("failed to decompress: {:?}", err))?;
+                Uint8Array::new(&data).to_vec()
+            }
+            None => self.data,
+        };
+        if Sha256::digest(&data).as_slice() != self.header.digest.as_slice() {
+            return Err("integrity check failed".to_owned());
+        }
+        Ok(Blob::new_with_options(&*data, Some(&self.header.file_typ)))
+    }
+}
+
+/// puts the chunks of every file back in order, whichever channel they came through
+#[derive(Default)]
+pub struct Reassembly {
+    files: HashMap<u32, IncomingFile>,
+    /// told to a sender that didn't get the receipt before the connection broke,
+    /// aborted files too so the sender doesn't try them again
+    complete: HashSet<u32>,
+}
+
+impl Reassembly {
+    /// files that started arriving and aren't complete yet
+    pub fn is_receiving(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    /// drops everything, the peer is gone
+    pub fn clear(&mut self) {
+        *self = Reassembly::default();
+    }
+
+    /// `None` for the frames that aren't file data or don't complete a file
+    pub fn receive(&mut self, frame: Frame) -> Option<Reassembled> {
+        self.evict_stale();
+        let id = match frame {
+            Frame::Header(header) => {
+                let id = header.id;
+                if self.complete.contains(&id) {
+                    return None;
+                }
+                self.files.entry(id).or_default().header = Some(header);
+                id
+            }
+            Frame::Chunk { transfer, offset, data } => {
+                if self.complete.contains(&transfer) {
+                    return None;
+                }
+                let file = self.files.entry(transfer).or_default();
+                // a resumed transfer may send some chunks twice
+                if !file.chunks.contains_key(&offset) {
+                    file.received += data.len() as u64;
+                    file.chunks.insert(offset, data);
+                }
+                transfer
+            }
+            Frame::Progress(_)
+            | Frame::Received(_)
+            | Frame::Commitment(_)
+            | Frame::Nonce(_)
+            | Frame::Filler => return None,
+        };
+        let file = self.files.get_mut(&id)?;
+        file.updated = Date::now();
+        let header = file.header.as_ref()?;
+        log!(format!("received {} of {} bytes of {}", file.received, header.size, header.name));
+        let overflows = file
+            .chunks
+            .last_key_value()
+            .is_some_and(|(offset, data)| *offset as u64 + data.len() as u64 > header.size);
+        if !overflows && file.received < header.size {
+            return None;
+        }
+        let file = self.files.remove(&id)?;
+        self.complete.insert(id);
+        let header = file.header?;
+        if overflows || file.received != header.size {
+            return Some(Reassembled::Aborted { id, name: header.name });
+        }
+        Some(Reassembled::Complete(ReceivedFile {
+            header,
+            data: file.chunks.into_values().flatten().collect(),
+        }))
+    }
+
+    /// drops the files the sender stopped sending, it starts over if it comes back later
+    fn evict_stale(&mut self) {
+        let now = Date::now();
+        self.files.retain(|id, file| {
+            let stale = now - file.updated > STALE_AFTER_MS;
+            if stale {
+                log!(format!("dropping transfer {} after no data for a while", id));
+            }
+            !stale
+        });
+    }
+
+    /// what arrived so far, keeping only the start of each file that has no gap in it
+    /// since the sender resumes from there
+    pub fn progress(&mut self) -> Progress {
+        self.evict_stale();
+        // the sender starts over with the files whose header got lost
+        self.files.retain(|_, file| file.header.is_some());
+        let mut partial = vec![];
+        for (id, file) in self.files.iter_mut() {
+            let mut contiguous = 0;
+            file.chunks.retain(|offset, data| {
+                let keep = *offset == contiguous;
+                if keep {
+                    contiguous += data.len() as u32;
+                }
+                keep
+            });
+            file.received = contiguous as u64;
+            partial.push((*id, contiguous));
+        }
+        Progress {
+            complete: self.complete.iter().copied().collect(),
+            partial,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use web_sys::RtcPeerConnection;
+
+/// this side's part in deriving the short authentication string both peers compare
+/// to make sure nobody swapped the DTLS fingerprints of the SDP on its way through
+/// the server. each side commits to a random nonce and only reveals it once it has
+/// the other's commitment, so a man in the middle has to pick its certificates and
+/// nonces before it can know the code either side will show
+pub struct Verification {
+    nonce: [u8; 16],
+    their_commitment: Option<Vec<u8>>,
+    their_nonce: Option<Vec<u8>>,
+}
+
+impl Default for Verification {
+    fn default() -> Self {
+        Verification {
+            nonce: *Uuid::new_v4().as_bytes(),
+            their_commitment: None,
+            their_nonce: None,
+        }
+    }
+}
+
+impl Verification {
+    /// sent as soon as the connection opens
+    pub fn commitment(&self) -> Vec<u8> {
+        Sha256::digest(self.nonce).to_vec()
+    }
+
+    /// the nonce to reveal, only for the first commitment of the other peer
+    pub fn commitment_received(&mut self, commitment: Vec<u8>) -> Option<Vec<u8>> {
+        if self.their_commitment.is_some() {
+            return None;
+        }
+        self.their_commitment = Some(commitment);
+        Some(self.nonce.to_vec())
+    }
+
+    /// `false` when the nonce doesn't match what the other peer committed to
+    pub fn nonce_received(&mut self, nonce: Vec<u8>) -> bool {
+        match &self.their_commitment {
+            Some(commitment) if Sha256::digest(&nonce).as_slice() == commitment.as_slice() => {
+                self.their_nonce = Some(nonce);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// known once both descriptions are set and both nonces revealed
+    pub fn short_authentication_string(&self, peer_connection: &RtcPeerConnection) -> Option<String> {
+        let local = peer_connection.local_description()?.sdp();
+        let remote = peer_connection.current_remote_description()?.sdp();
+        Some(short_authentication_string(
+            (&fingerprint(&local)?, &self.nonce),
+            (&fingerprint(&remote)?, self.their_nonce.as_ref()?),
+        ))
+    }
+}
+
+/// six digits from the fingerprint and nonce of both sides
+fn short_authentication_string(local: (&str, &[u8]), remote: (&str, &[u8])) -> String {
+    let mut sides = [local, remote];
+    // both peers have to hash the sides in the same order
+    sides.sort();
+    let mut hasher = Sha256::new();
+    for (fingerprint, nonce) in sides {
+        hasher.update(fingerprint);
+        hasher.update("\n");
+        hasher.update(nonce);
+    }
+    let digest = hasher.finalize();
+    let code = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+    format!("{:03} {:03}", code / 1000, code % 1000)
+}
+
+/// value of the first `a=fingerprint:` line, e.g. `sha-256 AB:CD:…`
+fn fingerprint(sdp: &str) -> Option<String> {
+    sdp.lines()
+        .find_map(|line| line.trim().strip_prefix("a=fingerprint:"))
+        .map(|fingerprint| fingerprint.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "SHA-256 AB:CD:EF";
+    const BOB: &str = "SHA-256 01:23:45";
+
+    #[test]
+    fn both_sides_show_the_same_code() {
+        let alice = (ALICE, &[1u8; 16][..]);
+        let bob = (BOB, &[2u8; 16][..]);
+        assert_eq!(short_authentication_string(alice, bob), short_authentication_string(bob, alice));
+    }
+
+    #[test]
+    fn code_changes_with_either_fingerprint() {
+        let nonces = ([1u8; 16], [2u8; 16]);
+        let code = short_authentication_string((ALICE, &nonces.0), (BOB, &nonces.1));
+        let swapped_local = short_authentication_string(("SHA-256 AB:CD:EE", &nonces.0), (BOB, &nonces.1));
+        let swapped_remote = short_authentication_string((ALICE, &nonces.0), ("SHA-256 01:23:46", &nonces.1));
+        assert_ne!(code, swapped_local);
+        assert_ne!(code, swapped_remote);
+    }
+
+    #[test]
+    fn nonce_has_to_match_the_commitment() {
+        let mut alice = Verification::default();
+        let bob = Verification::default();
+        assert!(!alice.nonce_received(bob.nonce.to_vec()), "revealed before committing");
+        let nonce = alice.commitment_received(bob.commitment()).unwrap();
+        assert_eq!(nonce, alice.nonce.to_vec());
+        assert!(alice.commitment_received(bob.commitment()).is_none(), "revealed twice");
+        assert!(!alice.nonce_received(vec![0; 16]));
+        assert!(alice.nonce_received(bob.nonce.to_vec()));
+    }
+}
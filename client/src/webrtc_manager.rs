@@ -1,10 +1,11 @@
-use std::{cell::{RefCell, Cell}, rc::Rc, collections::VecDeque};
+use std::{cell::{RefCell, Cell}, rc::Rc};
 
 use gloo::{
     console::{error, log},
-    file::Blob,
+    timers::{callback::Timeout, future::TimeoutFuture},
 };
-use js_sys::{Array, ArrayBuffer, Object, Reflect, Uint8Array};
+use futures::{channel::mpsc, SinkExt};
+use js_sys::{Array, ArrayBuffer, Date, Object, Reflect, Uint8Array};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Sender;
 use uuid::Uuid;
@@ -13,21 +14,38 @@ use wasm_bindgen_futures::JsFuture;
 use web_sys::{
     MessageEvent, RtcConfiguration, RtcDataChannel, RtcDataChannelEvent, RtcDataChannelInit,
     RtcIceCandidate, RtcIceCandidateInit, RtcPeerConnection, RtcPeerConnectionIceEvent, RtcSdpType,
-    RtcSessionDescriptionInit, Event,
+    RtcSessionDescriptionInit, Event, RtcIceConnectionState, RtcSignalingState,
+    RtcDataChannelState, RtcOfferOptions, RtcPeerConnectionState, RtcDataChannelType,
 };
-use yew::platform::spawn_local;
+use yew::{platform::spawn_local, Callback};
+use yewdux::prelude::Dispatch;
 
-use crate::components::atoms::{
-    avatar::{FileData, send_till_buffer_full},
-    messages::{
-        AppMessage::{self, *},
-        ClientMessage, ServerMessage, SignalingMessage,
+use crate::{
+    components::atoms::{
+        connection_state::{ConnectionStates, ConnectionStatus},
+        ice_config_state::IceConfig,
+        messages::{
+            AppMessage::{self, *},
+            ClientMessage, RelayFrame, ServerMessage, SignalingMessage,
+        },
+        other_peers_state::WebRTCRole,
     },
-    other_peers_state::WebRTCRole,
+    pacing::{Pacer, Pacing, RELAY_CHUNK_SIZE},
+    stats::{self, ConnectionStats},
+    transfer::{Frame, OutgoingTransfer, Reassembled, Reassembly, SendQueue},
+    verification::Verification,
 };
-pub const MAX_CHUNK_SIZE: u32 = 16384;
-const BUFFERED_AMOUNT_LOW_THRESHOLD: u32 = MAX_CHUNK_SIZE * 4;
-const STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+/// data channels a transfer is striped across
+pub const DATA_CHANNELS: usize = 4;
+/// ICE restarts attempted before the connection is torn down and rebuilt
+const MAX_ICE_RESTARTS: u32 = 2;
+/// times the connection is rebuilt from this side before relaying through the server,
+/// counted again from zero once a rebuilt connection gets through
+const MAX_REBUILDS: u32 = 1;
+/// how long a `disconnected` ICE state may last before it is treated as failed
+const DISCONNECTED_TIMEOUT_MS: u32 = 5000;
+/// how often an idle relay looks for new frames in the queue
+const RELAY_IDLE_MS: u32 = 50;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IceCandidate {
@@ -35,183 +53,488 @@ pub struct IceCandidate {
     pub sdp_mid: Option<String>,
     pub sdp_m_line_index: Option<u16>,
 }
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+/// what a connection needs to recover from a broken network path
+#[derive(Clone)]
+struct Recovery {
+    tx: Sender<AppMessage>,
+    other_peer: Uuid,
+    /// the websocket relay, `None` until the server sent the peer list
+    relay: Option<mpsc::Sender<RelayFrame>>,
+    on_relayed: Callback<()>,
+    on_reconnect: Callback<()>,
+    /// outlives the connection, which is replaced on every rebuild
+    rebuilds: Rc<Cell<u32>>,
+}
+
 #[derive(Clone)]
 pub struct WebRtcConnection {
     pub peer_connection: RtcPeerConnection,
-    pub data_channel: RtcDataChannel,
+    /// transfers are striped across these, all on the same peer connection
+    data_channels: Rc<Vec<RtcDataChannel>>,
+    /// set when the first data channel opens, this side then tells the other
+    /// what it received and commits to its verification nonce
+    opened: Rc<Cell<bool>>,
+    verification: Rc<RefCell<Verification>>,
+    /// how many of the data channels the transfers are striped across
+    active_channels: Rc<Cell<usize>>,
+    /// set once ICE failed, files are then tunnelled through the signaling server
+    pub relayed: Rc<Cell<bool>>,
+    making_offer: Rc<Cell<bool>>,
+    ignore_offer: Rc<Cell<bool>>,
+    pending_candidates: Rc<RefCell<Vec<IceCandidate>>>,
+    /// files waiting for room in the data channels and the files being received,
+    /// shared with the connection that replaces this one so a transfer carries on
+    /// from what the receiver says it has
+    outgoing: Rc<RefCell<SendQueue>>,
+    incoming: Rc<RefCell<Reassembly>>,
+    pacer: Rc<Pacer>,
+    ice_restarts: Rc<Cell<u32>>,
+    /// stops the signaling tasks once the connection has been replaced
+    closed: Rc<Cell<bool>>,
+}
+
+impl PartialEq for WebRtcConnection {
+    fn eq(&self, other: &Self) -> bool {
+        self.peer_connection == other.peer_connection
+    }
 }
 
 impl WebRtcConnection {
-    pub fn new() -> Self {
+    pub fn new(outgoing: Rc<RefCell<SendQueue>>, incoming: Rc<RefCell<Reassembly>>) -> Self {
         let peer_connection = Self::create_peer_connection();
-        let data_channel = Self::create_data_channel(&peer_connection);
+        let data_channels = Self::create_data_channels(&peer_connection);
+        // what the previous connection sent may not have arrived
+        outgoing.borrow_mut().pause();
 
         WebRtcConnection {
             peer_connection,
-            data_channel,
+            data_channels: Rc::new(data_channels),
+            opened: Rc::new(Cell::new(false)),
+            verification: Rc::new(RefCell::new(Verification::default())),
+            active_channels: Rc::new(Cell::new(DATA_CHANNELS)),
+            relayed: Rc::new(Cell::new(false)),
+            making_offer: Rc::new(Cell::new(false)),
+            ignore_offer: Rc::new(Cell::new(false)),
+            pending_candidates: Rc::new(RefCell::new(Vec::new())),
+            outgoing,
+            incoming,
+            pacer: Rc::new(Pacer::default()),
+            ice_restarts: Rc::new(Cell::new(0)),
+            closed: Rc::new(Cell::new(false)),
         }
     }
 
-    pub fn init(&self, tx: Sender<AppMessage>, other_peer: Uuid, role: WebRTCRole) {
+    /// `on_reconnect` asks the owner to replace this connection with a new one,
+    /// which is only done from this side while `can_reconnect` holds
+    pub fn init(
+        &self,
+        tx: Sender<AppMessage>,
+        other_peer: Uuid,
+        role: WebRTCRole,
+        relay: Option<mpsc::Sender<RelayFrame>>,
+        on_relayed: Callback<()>,
+        on_reconnect: Callback<()>,
+        rebuilds: Rc<Cell<u32>>,
+    ) {
+        Self::set_status(other_peer, ConnectionStatus::Connecting);
+        // a rebuilt connection has new fingerprints and nonces
+        Dispatch::<ConnectionStates>::new().reduce_mut(|states| {
+            states.codes.remove(&other_peer);
+        });
         self.set_on_message_callback(other_peer);
         self.set_on_error_callback();
         self.set_on_ice_candidate(tx.clone(), other_peer);
-        self.add_ice_candidate(tx.clone());
+        self.add_ice_candidate(tx.clone(), other_peer);
         self.set_peeer_connection_on_data_channel(other_peer);
         self.set_on_open();
+        self.set_on_buffered_amount_low();
         self.set_on_close_callback();
-        self.exchange_offers(tx, other_peer, role);
-        self.data_channel
-            .set_buffered_amount_low_threshold(BUFFERED_AMOUNT_LOW_THRESHOLD);
+        let recovery = Recovery {
+            tx: tx.clone(),
+            other_peer,
+            relay,
+            on_relayed,
+            on_reconnect: on_reconnect.clone(),
+            rebuilds,
+        };
+        self.set_on_ice_connection_state_change(recovery.clone());
+        self.set_on_connection_state_change(recovery.clone());
+        self.receive_reconnect(tx, other_peer, on_reconnect);
+        self.receive_relayed_frames(recovery.clone());
+        self.negotiate(role, recovery);
+    }
+
+    /// queues the file, it is sliced into chunks as room frees up in the data channels
+    pub fn send(&self, transfer: OutgoingTransfer) {
+        self.outgoing.borrow_mut().push_file(transfer);
+        self.send_till_buffer_full();
     }
 
-    pub fn exchange_offers(&self, tx: Sender<AppMessage>, other_peer: Uuid, role: WebRTCRole) {
+    /// sends `bytes` of filler striped across `channels` data channels with the given
+    /// pacing, resolving to the throughput in bytes per second once the buffers drained.
+    /// refused while files are on their way, they and the filler would slow each other down
+    pub async fn benchmark(&self, pacing: Pacing, channels: usize, bytes: u32) -> Result<f64, &'static str> {
+        if !self.is_open() || self.relayed.get() {
+            return Err("not connected directly");
+        }
+        if self.outgoing.borrow().has_files() || self.incoming.borrow().is_receiving() {
+            return Err("waiting for a transfer to finish");
+        }
+        let previous = (self.pacer.mode(), self.active_channels.get());
+        self.active_channels.set(channels.clamp(1, DATA_CHANNELS));
+        self.pacer.set_mode(pacing, self.active_data_channels());
+        let start = Date::now();
+        self.outgoing.borrow_mut().push_filler(bytes);
+        self.send_till_buffer_full();
+        let mut drained = true;
+        while !self.outgoing.borrow().is_empty()
+            || self.data_channels.iter().any(|data_channel| data_channel.buffered_amount() > 0)
+        {
+            if self.closed.get() || !self.is_open() {
+                drained = false;
+                break;
+            }
+            TimeoutFuture::new(10).await;
+        }
+        let elapsed = (Date::now() - start) / 1000.0;
+        self.active_channels.set(previous.1);
+        self.pacer.set_mode(previous.0, self.active_data_channels());
+        if !drained {
+            return Err("the connection dropped");
+        }
+        Ok(bytes as f64 / elapsed)
+    }
+
+    fn is_open(&self) -> bool {
+        self.data_channels
+            .iter()
+            .all(|data_channel| data_channel.ready_state() == RtcDataChannelState::Open)
+    }
+
+    fn active_data_channels(&self) -> &[RtcDataChannel] {
+        &self.data_channels[..self.active_channels.get()]
+    }
+
+    /// hands frames to the active data channels in turn, so consecutive
+    /// chunks travel on different SCTP streams
+    fn send_till_buffer_full(&self) {
+        if self.relayed.get() {
+            return;
+        }
+        let channels: Vec<_> = self
+            .active_data_channels()
+            .iter()
+            .filter(|data_channel| data_channel.ready_state() == RtcDataChannelState::Open)
+            .collect();
+        let mut sent = true;
+        while sent {
+            sent = false;
+            for data_channel in &channels {
+                if data_channel.buffered_amount() >= self.pacer.high_watermark() {
+                    continue;
+                }
+                let frame = self.outgoing.borrow_mut().next_frame(self.pacer.chunk_size());
+                let Some(frame) = frame else { break };
+                // the receiver's progress tells what to send again on the next connection
+                if data_channel.send_with_array_buffer(&frame).is_err() {
+                    continue;
+                }
+                self.pacer.on_sent(frame.byte_length());
+                sent = true;
+            }
+        }
+    }
+
+    /// what was still buffered is lost, the next connection resumes from the
+    /// progress the receiver reports
+    pub fn close(&self) {
+        self.closed.set(true);
+        for data_channel in self.data_channels.iter() {
+            data_channel.close();
+        }
+        self.peer_connection.close();
+    }
+
+    /// tells the other peer what arrived so far, its queue waits for this before sending files
+    fn send_progress(&self) {
+        let progress = self.incoming.borrow_mut().progress();
+        self.outgoing
+            .borrow_mut()
+            .push_control(Frame::encode_progress(&progress));
+        self.send_till_buffer_full();
+    }
+
+    /// perfect negotiation: either side sends an offer whenever `negotiationneeded`
+    /// fires, and when both offers cross the impolite peer ignores the incoming one
+    /// while the polite peer rolls its own back and answers, a description that
+    /// can't be created or applied sends the connection down the recovery path
+    fn negotiate(&self, role: WebRTCRole, recovery: Recovery) {
+        log!(format!("this peer is {:?} toward the contacted peer", role));
+        self.set_on_negotiation_needed(recovery.clone());
+        self.receive_descriptions(recovery, role == WebRTCRole::Polite);
+    }
+
+    fn set_on_negotiation_needed(&self, recovery: Recovery) {
+        let connection = self.clone();
+        let on_negotiation_needed: Box<dyn FnMut(Event)> = Box::new(move |_| {
+            let connection = connection.clone();
+            let recovery = recovery.clone();
+            spawn_local(async move {
+                connection.making_offer.set(true);
+                let offer = Self::create_offer(&connection.peer_connection).await;
+                connection.making_offer.set(false);
+                let offer = match offer {
+                    Ok(offer) => offer,
+                    Err(error) => {
+                        log!("creating an offer failed", error);
+                        connection.recover(&recovery);
+                        return;
+                    }
+                };
+                let offer_msg = CltMsg(ClientMessage::SignalingMessage(SignalingMessage::Offer(
+                    recovery.other_peer,
+                    offer,
+                )));
+                recovery
+                    .tx
+                    .send(offer_msg)
+                    .map_err(|err| log!(format!("{:?}", err)))
+                    .expect("error sending offer msg");
+            });
+        });
+        let on_negotiation_needed = Closure::wrap(on_negotiation_needed);
+        self.peer_connection
+            .set_onnegotiationneeded(Some(on_negotiation_needed.as_ref().unchecked_ref()));
+        on_negotiation_needed.forget();
+    }
+
+    fn receive_descriptions(&self, recovery: Recovery, polite: bool) {
+        let connection = self.clone();
         let peer_connection = self.peer_connection.clone();
+        let making_offer = self.making_offer.clone();
+        let ignore_offer = self.ignore_offer.clone();
+        let pending_candidates = self.pending_candidates.clone();
+        let tx = recovery.tx.clone();
+        let other_peer = recovery.other_peer;
+        let mut rx = tx.subscribe();
+        let closed = self.closed.clone();
         spawn_local(async move {
-            log!(format!("the contacted peer is {:?}", role));
-            match role {
-                WebRTCRole::Client => {
-                    let offer = Self::create_offer(&peer_connection).await;
-                    let offer_msg = CltMsg(ClientMessage::SignalingMessage(
-                        SignalingMessage::Offer(other_peer, offer),
-                    ));
-                    tx.send(offer_msg)
-                        .map_err(|err| log!(format!("{:?}", err)))
-                        .expect("error sending offer msg");
-                    let mut rx = tx.subscribe();
-                    while let Ok(msg) = rx.recv().await {
-                        if let SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::Answer(
-                            responded_peer,
-                            answer,
-                        ))) = msg
-                        {
-                            if other_peer == responded_peer {
-                                Self::receive_answer(&peer_connection, answer).await;
-                                break;
-                            }
-                        }
+            while let Ok(msg) = rx.recv().await {
+                if closed.get() {
+                    break;
+                }
+                let (sdp_type, sdp) = match msg {
+                    SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::Offer(
+                        signaler_peer,
+                        offer,
+                    ))) if signaler_peer == other_peer => (RtcSdpType::Offer, offer),
+                    SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::Answer(
+                        responded_peer,
+                        answer,
+                    ))) if responded_peer == other_peer => (RtcSdpType::Answer, answer),
+                    _ => continue,
+                };
+                log!(format!("{:?} received from the contacted peer", sdp_type));
+
+                let offer_collision = sdp_type == RtcSdpType::Offer
+                    && (making_offer.get()
+                        || peer_connection.signaling_state() != RtcSignalingState::Stable);
+                ignore_offer.set(!polite && offer_collision);
+                if ignore_offer.get() {
+                    log!("offer collision, ignoring the contacted peer's offer");
+                    pending_candidates.borrow_mut().clear();
+                    continue;
+                }
+                if offer_collision {
+                    log!("offer collision, rolling back this peer's offer");
+                    if let Err(error) = Self::rollback(&peer_connection).await {
+                        log!("rolling back failed", error);
+                        connection.recover(&recovery);
+                        continue;
                     }
                 }
-                WebRTCRole::Server => {
-                    let mut rx = tx.subscribe();
-                    while let Ok(msg) = rx.recv().await {
-                        log!(format!(" offer received{:?}", msg));
-                        if let SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::Offer(
-                            signaler_peer,
-                            offer,
-                        ))) = msg
-                        {
-                            if other_peer == signaler_peer {
-                                let answer = Self::create_answer(&peer_connection, offer).await;
-                                let answer_msg = CltMsg(ClientMessage::SignalingMessage(
-                                    SignalingMessage::Answer(signaler_peer, answer),
-                                ));
-                                tx.send(answer_msg).expect("error sendig answer");
-                                break;
-                            }
-                        }
+
+                if let Err(error) = Self::set_remote_description(&peer_connection, sdp_type, &sdp).await {
+                    log!(format!("setting the {:?} failed", sdp_type), error);
+                    // e.g. the answer to an offer of ours the polite peer rolled back
+                    if !ignore_offer.get() {
+                        connection.recover(&recovery);
                     }
+                    continue;
+                }
+                Self::add_pending_candidates(&peer_connection, &pending_candidates, false).await;
+                if sdp_type == RtcSdpType::Offer {
+                    let answer = match Self::create_answer(&peer_connection).await {
+                        Ok(answer) => answer,
+                        Err(error) => {
+                            log!("answering failed", error);
+                            connection.recover(&recovery);
+                            continue;
+                        }
+                    };
+                    let answer_msg = CltMsg(ClientMessage::SignalingMessage(
+                        SignalingMessage::Answer(other_peer, answer),
+                    ));
+                    tx.send(answer_msg).expect("error sendig answer");
                 }
             }
         });
     }
 
-    async fn create_offer(peer_connection: &RtcPeerConnection) -> String {
-        let offer = JsFuture::from(peer_connection.create_offer())
-            .await
-            .expect("error creating offer");
-        let offer = Reflect::get(&offer, &JsValue::from_str("sdp"))
-            .expect("error creating offer sdp")
+    async fn create_offer(peer_connection: &RtcPeerConnection) -> Result<String, JsValue> {
+        Self::create_offer_with_options(peer_connection, &RtcOfferOptions::new()).await
+    }
+
+    async fn create_offer_with_options(
+        peer_connection: &RtcPeerConnection,
+        options: &RtcOfferOptions,
+    ) -> Result<String, JsValue> {
+        let offer = JsFuture::from(peer_connection.create_offer_with_rtc_offer_options(options)).await?;
+        let offer = Reflect::get(&offer, &JsValue::from_str("sdp"))?
             .as_string()
-            .unwrap();
+            .ok_or("offer without an sdp")?;
         log!("created offer");
         let mut offer_obj = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
         offer_obj.sdp(&offer);
-        let sld_promise = peer_connection.set_local_description(&offer_obj);
-        JsFuture::from(sld_promise)
-            .await
-            .expect("error sld_promise");
+        JsFuture::from(peer_connection.set_local_description(&offer_obj)).await?;
         log!("pc1: state {:?}", peer_connection.signaling_state());
-        offer
+        Ok(offer)
     }
-    async fn create_answer(peer_connection: &RtcPeerConnection, offer: String) -> String {
-        let mut remote_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
-        remote_session_description.sdp(&offer);
-        JsFuture::from(peer_connection.set_remote_description(&remote_session_description))
-            .await
-            .expect("error set remote desreption");
 
-        let answer = JsFuture::from(peer_connection.create_answer())
-            .await
-            .expect("error creating answer");
-        let answer = Reflect::get(&answer, &JsValue::from_str("sdp"))
-            .expect("error Relect::get answer")
+    async fn create_answer(peer_connection: &RtcPeerConnection) -> Result<String, JsValue> {
+        let answer = JsFuture::from(peer_connection.create_answer()).await?;
+        let answer = Reflect::get(&answer, &JsValue::from_str("sdp"))?
             .as_string()
-            .expect("failed to represent object value as string");
+            .ok_or("answer without an sdp")?;
 
         let mut local_session_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
         local_session_description.sdp(&answer);
-        JsFuture::from(peer_connection.set_local_description(&local_session_description))
-            .await
-            .expect("failed to set local description");
+        JsFuture::from(peer_connection.set_local_description(&local_session_description)).await?;
 
-        answer
+        Ok(answer)
     }
 
-    async fn receive_answer(peer_connection: &RtcPeerConnection, answer: String) {
-        let mut answer_obj = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-        answer_obj.sdp(&answer);
-        let srd_promise = peer_connection.set_remote_description(&answer_obj);
-        JsFuture::from(srd_promise)
-            .await
-            .expect("error receiving answer");
+    async fn set_remote_description(
+        peer_connection: &RtcPeerConnection,
+        sdp_type: RtcSdpType,
+        sdp: &str,
+    ) -> Result<(), JsValue> {
+        let mut remote_session_description = RtcSessionDescriptionInit::new(sdp_type);
+        remote_session_description.sdp(sdp);
+        JsFuture::from(peer_connection.set_remote_description(&remote_session_description)).await?;
         log!("pc1: state {:?}", peer_connection.signaling_state());
+        Ok(())
     }
 
-    fn add_ice_candidate(&self, tx: Sender<AppMessage>) {
+    async fn rollback(peer_connection: &RtcPeerConnection) -> Result<(), JsValue> {
+        let rollback = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
+        JsFuture::from(peer_connection.set_local_description(&rollback)).await?;
+        Ok(())
+    }
+
+    /// candidates arriving before the remote description are queued
+    /// and added once `set_remote_description` has completed
+    fn add_ice_candidate(&self, tx: Sender<AppMessage>, other_peer: Uuid) {
         let peer_connection = self.peer_connection.clone();
+        let ignore_offer = self.ignore_offer.clone();
+        let pending_candidates = self.pending_candidates.clone();
+        let closed = self.closed.clone();
         let mut rx = tx.subscribe();
         spawn_local(async move {
             while let Ok(msg) = rx.recv().await {
+                if closed.get() {
+                    break;
+                }
                 if let SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::IceCandidate(
-                    _,
+                    signaler_peer,
                     ice_candidate,
                 ))) = msg
                 {
-                    let mut rtc_candidate = RtcIceCandidateInit::new("");
-                    rtc_candidate.candidate(&ice_candidate.candidate);
-                    rtc_candidate.sdp_m_line_index(ice_candidate.sdp_m_line_index);
-                    rtc_candidate.sdp_mid(ice_candidate.sdp_mid.as_deref());
-
-                    let rtc_candidate = RtcIceCandidate::new(&rtc_candidate)
-                        .expect("failed to create new RtcIceCandidate");
-                    JsFuture::from(
-                        peer_connection
-                            .add_ice_candidate_with_opt_rtc_ice_candidate(Some(&rtc_candidate)),
-                    )
-                    .await
-                    .expect("failed to add ICE candidate");
-                    log!("ice candidate added");
+                    if signaler_peer != other_peer {
+                        continue;
+                    }
+                    if peer_connection.remote_description().is_none() {
+                        pending_candidates.borrow_mut().push(ice_candidate);
+                        continue;
+                    }
+                    Self::add_remote_candidate(&peer_connection, ice_candidate, ignore_offer.get())
+                        .await;
                 }
             }
         });
     }
 
+    async fn add_pending_candidates(
+        peer_connection: &RtcPeerConnection,
+        pending_candidates: &RefCell<Vec<IceCandidate>>,
+        ignore_offer: bool,
+    ) {
+        let candidates: Vec<IceCandidate> = pending_candidates.borrow_mut().drain(..).collect();
+        for ice_candidate in candidates {
+            Self::add_remote_candidate(peer_connection, ice_candidate, ignore_offer).await;
+        }
+    }
+
+    async fn add_remote_candidate(
+        peer_connection: &RtcPeerConnection,
+        ice_candidate: IceCandidate,
+        ignore_offer: bool,
+    ) {
+        // an empty candidate marks the end of the other peer's candidates
+        let rtc_candidate = if ice_candidate.candidate.is_empty() {
+            None
+        } else {
+            let mut rtc_candidate = RtcIceCandidateInit::new("");
+            rtc_candidate.candidate(&ice_candidate.candidate);
+            rtc_candidate.sdp_m_line_index(ice_candidate.sdp_m_line_index);
+            rtc_candidate.sdp_mid(ice_candidate.sdp_mid.as_deref());
+            Some(
+                RtcIceCandidate::new(&rtc_candidate)
+                    .expect("failed to create new RtcIceCandidate"),
+            )
+        };
+        let added = JsFuture::from(
+            peer_connection.add_ice_candidate_with_opt_rtc_ice_candidate(rtc_candidate.as_ref()),
+        )
+        .await;
+        match added {
+            Ok(_) if rtc_candidate.is_none() => log!("end of ice candidates"),
+            Ok(_) => log!("ice candidate added"),
+            // candidates belonging to an offer we ignored are expected to fail
+            Err(_) if ignore_offer => {}
+            Err(err) => error!(format!("failed to add ICE candidate: {:?}", err)),
+        }
+    }
+
     fn set_on_ice_candidate(&self, tx: Sender<AppMessage>, other_peer: Uuid) {
         let on_ice_candidate: Box<dyn FnMut(RtcPeerConnectionIceEvent)> =
             Box::new(move |ev: RtcPeerConnectionIceEvent| {
-                if let Some(candidate) = ev.candidate() {
-                    let ice_candidate = IceCandidate {
+                // a null candidate means gathering is complete, it is forwarded
+                // as an empty one so the other peer gets end-of-candidates
+                let ice_candidate = match ev.candidate() {
+                    Some(candidate) => IceCandidate {
                         candidate: candidate.candidate(),
                         sdp_mid: candidate.sdp_mid(),
                         sdp_m_line_index: candidate.sdp_m_line_index(),
-                    };
-                    let signaling_message = CltMsg(ClientMessage::SignalingMessage(
-                        SignalingMessage::IceCandidate(other_peer, ice_candidate),
-                    ));
-                    tx.send(signaling_message)
-                        .expect("error sendig form set on ice candidate");
-                }
+                    },
+                    None => IceCandidate {
+                        candidate: String::new(),
+                        sdp_mid: None,
+                        sdp_m_line_index: None,
+                    },
+                };
+                let signaling_message = CltMsg(ClientMessage::SignalingMessage(
+                    SignalingMessage::IceCandidate(other_peer, ice_candidate),
+                ));
+                tx.send(signaling_message)
+                    .expect("error sendig form set on ice candidate");
             });
         let on_ice_candidate = Closure::wrap(on_ice_candidate);
         self.peer_connection
@@ -220,32 +543,29 @@ impl WebRtcConnection {
     }
 
     fn set_peeer_connection_on_data_channel(&self, other_peer: Uuid) {
+        let connection = self.clone();
         let on_datachannel: Box<dyn FnMut(RtcDataChannelEvent)> =
             Box::new(move |data_channel_event: RtcDataChannelEvent| {
                 log!("on_data_channel on this peer excuted");
                 let data_channel = data_channel_event.channel();
-                // let data_channel_clone = data_channel.clone();
+                data_channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+                let connection = connection.clone();
                 let onmessage_callback = Closure::wrap(Box::new(move |ev: MessageEvent| {
-                    log!(ev.data().is_array(), ev.data().is_object());
-                    let message = ev.data().dyn_into::<ArrayBuffer>().ok();
-                    log!(
-                        "message recv fonm on data channel",
-                        format!("{:?}", message.clone())
-                    );
-                    if let Some(message) = message {
-                        log!(
-                            "message from datachannel (will call on_message)",
-                            format!("{:?}", message.byte_length())
-                        );
+                    match ev.data().dyn_into::<ArrayBuffer>() {
+                        Ok(message) => {
+                            connection.receive_frame(other_peer, &Uint8Array::new(&message).to_vec())
+                        }
+                        Err(message) => log!(
+                            "this peer received: {}",
+                            message,
+                            "from",
+                            other_peer.to_string()
+                        ),
                     }
                 })
                     as Box<dyn FnMut(MessageEvent)>);
                 data_channel.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
                 onmessage_callback.forget();
-                log!("this peer sent: it's working ya rab ");
-                data_channel
-                    .send_with_str("it's working ya rab")
-                    .expect("error sending ya rab");
             });
 
         let on_datachannel = Closure::wrap(on_datachannel);
@@ -255,96 +575,413 @@ impl WebRtcConnection {
     }
 
     fn set_on_open(&self) {
-        let on_open_callback: Box<dyn FnMut(JsValue)> = Box::new(move |_| {
-            log!("data channel is open");
-        });
-        let on_open_callback = Closure::wrap(on_open_callback);
-        self.data_channel
-            .set_onopen(Some(on_open_callback.as_ref().unchecked_ref()));
-        on_open_callback.forget();
+        for data_channel in self.data_channels.iter() {
+            let connection = self.clone();
+            let on_open_callback: Box<dyn FnMut(JsValue)> = Box::new(move |_| {
+                log!("data channel is open");
+                connection
+                    .pacer
+                    .configure(&connection.peer_connection, connection.active_data_channels());
+                log!(format!(
+                    "chunk size: {}, buffered amount watermark: {}",
+                    connection.pacer.chunk_size(),
+                    connection.pacer.high_watermark()
+                ));
+                // the other peer resumes its transfers from this
+                if !connection.opened.replace(true) {
+                    let commitment = connection.verification.borrow().commitment();
+                    connection
+                        .outgoing
+                        .borrow_mut()
+                        .push_control(Frame::encode_commitment(&commitment));
+                    connection.send_progress();
+                }
+                connection.send_till_buffer_full();
+            });
+            let on_open_callback = Closure::wrap(on_open_callback);
+            data_channel.set_onopen(Some(on_open_callback.as_ref().unchecked_ref()));
+            on_open_callback.forget();
+        }
+    }
+
+    fn set_on_buffered_amount_low(&self) {
+        for data_channel in self.data_channels.iter() {
+            let connection = self.clone();
+            let on_buffered_amount_low: Box<dyn FnMut(Event)> = Box::new(move |_| {
+                connection
+                    .pacer
+                    .on_buffered_amount_low(connection.active_data_channels());
+                connection.send_till_buffer_full();
+            });
+            let on_buffered_amount_low = Closure::wrap(on_buffered_amount_low);
+            data_channel
+                .set_onbufferedamountlow(Some(on_buffered_amount_low.as_ref().unchecked_ref()));
+            on_buffered_amount_low.forget();
+        }
     }
 
     fn set_on_message_callback(&self, other_peer: Uuid) {
-        let data_channel_clone = self.data_channel.clone();
-        let on_message_callback: Box<dyn FnMut(MessageEvent)> =
-            Box::new(move |ev: MessageEvent| {
-                let message = ev.data().dyn_into::<Uint8Array>().ok();
-                log!("message rec", format!("{:?}", message));
-                if let Some(message) =
-                    message.and_then(|t| rmp_serde::from_slice::<FileData>(&t.to_vec()).ok())
-                {
-                    log!("message from datachannel (will call on_message)");
-                    let blob = Blob::new_with_options(&*message.data, Some(&message.file_typ));
-                    log!(blob.raw_mime_type());
-                    log!(message.name);
-                    //tbd
-                }
-                match ev.data().as_string() {
-                    Some(message) => {
+        for data_channel in self.data_channels.iter() {
+            let connection = self.clone();
+            let on_message_callback: Box<dyn FnMut(MessageEvent)> =
+                Box::new(move |ev: MessageEvent| {
+                    if let Ok(message) = ev.data().dyn_into::<ArrayBuffer>() {
+                        connection.receive_frame(other_peer, &Uint8Array::new(&message).to_vec());
+                        return;
+                    }
+                    if let Some(message) = ev.data().as_string() {
                         log!(
                             "this peer received: {}",
                             message,
                             "from",
                             other_peer.to_string()
                         );
-                        log!("this peer sent: ping to: ", other_peer.to_string());
-                        data_channel_clone
-                            .send_with_str("ping")
-                            .expect("error sending form on message callback");
                     }
-                    None => todo!(),
+                });
+            let on_message_callback = Closure::wrap(on_message_callback);
+            data_channel.set_onmessage(Some(on_message_callback.as_ref().unchecked_ref()));
+            on_message_callback.forget();
+        }
+    }
+
+    fn receive_frame(&self, other_peer: Uuid, bytes: &[u8]) {
+        let Some(frame) = Frame::decode(bytes) else {
+            return;
+        };
+        match frame {
+            Frame::Progress(progress) => {
+                self.outgoing.borrow_mut().resume(&progress);
+                self.send_till_buffer_full();
+                return;
+            }
+            Frame::Received(transfer) => {
+                self.outgoing.borrow_mut().confirm(transfer);
+                return;
+            }
+            Frame::Commitment(commitment) => {
+                let nonce = self.verification.borrow_mut().commitment_received(commitment);
+                if let Some(nonce) = nonce {
+                    self.outgoing.borrow_mut().push_control(Frame::encode_nonce(&nonce));
+                    self.send_till_buffer_full();
                 }
+                return;
+            }
+            Frame::Nonce(nonce) => {
+                self.nonce_received(other_peer, nonce);
+                return;
+            }
+            Frame::Filler => return,
+            _ => {}
+        }
+        let reassembled = self.incoming.borrow_mut().receive(frame);
+        let file = match reassembled {
+            Some(Reassembled::Complete(file)) => file,
+            Some(Reassembled::Aborted { id, name }) => {
+                error!(format!("dropping {}: more data than announced", name));
+                self.outgoing.borrow_mut().push_control(Frame::encode_received(id));
+                self.send_till_buffer_full();
+                return;
+            }
+            None => return,
+        };
+        self.outgoing
+            .borrow_mut()
+            .push_control(Frame::encode_received(file.header.id));
+        self.send_till_buffer_full();
+        spawn_local(async move {
+            let name = file.header.name.clone();
+            match file.verify().await {
+                Ok(blob) => log!("file received:", name, blob.raw_mime_type(), blob.size()),
+                Err(err) => error!(format!("dropping {}: {}", name, err)),
+            }
+            //tbd
+        });
+    }
+
+    /// publishes the short authentication string once the other peer's nonce checks out
+    fn nonce_received(&self, other_peer: Uuid, nonce: Vec<u8>) {
+        let mut verification = self.verification.borrow_mut();
+        if !verification.nonce_received(nonce) {
+            error!("the verification nonce doesn't match its commitment");
+            return;
+        }
+        if let Some(code) = verification.short_authentication_string(&self.peer_connection) {
+            Dispatch::<ConnectionStates>::new().reduce_mut(|states| {
+                states.codes.insert(other_peer, code);
             });
-        let on_message_callback = Closure::wrap(on_message_callback);
-        self.data_channel
-            .set_onmessage(Some(on_message_callback.as_ref().unchecked_ref()));
-        on_message_callback.forget();
+        }
     }
 
-    fn set_on_error_callback(&self) {
-        let on_error: Box<dyn FnMut(JsValue)> = Box::new(move |data_channel_error| {
-            error!("data channel error: {:?}", data_channel_error);
+    /// the other peer relaying means it gave up on the direct path, this one follows
+    fn receive_relayed_frames(&self, recovery: Recovery) {
+        let connection = self.clone();
+        let mut rx = recovery.tx.subscribe();
+        spawn_local(async move {
+            while let Ok(msg) = rx.recv().await {
+                if connection.closed.get() {
+                    break;
+                }
+                if let Relay(frame) = msg {
+                    if frame.peer == recovery.other_peer {
+                        connection.fall_back_to_relay(&recovery);
+                        log!("relayed bytes received: ", frame.data.len());
+                        connection.receive_frame(recovery.other_peer, &frame.data);
+                    }
+                }
+            }
         });
-        let on_error = Closure::wrap(on_error);
-        self.data_channel
-            .set_onerror(Some(on_error.as_ref().unchecked_ref()));
-        on_error.forget();
     }
 
-    fn set_on_close_callback(&self) {
-        let on_close: Box<dyn FnMut(JsValue)> = Box::new(move |_| {
-            log!("data channel closed");
+    /// moves the queue onto the websocket relay, what the data channels had
+    /// buffered is sent again once the other peer's progress comes through the relay
+    fn fall_back_to_relay(&self, recovery: &Recovery) {
+        if self.relayed.replace(true) {
+            return;
+        }
+        Self::set_status(recovery.other_peer, ConnectionStatus::Relayed);
+        recovery.on_relayed.emit(());
+        let Some(relay) = recovery.relay.clone() else {
+            error!("no relay to fall back to");
+            return;
+        };
+        self.outgoing.borrow_mut().pause();
+        self.send_progress();
+        let connection = self.clone();
+        let other_peer = recovery.other_peer;
+        spawn_local(async move { connection.relay(relay, other_peer).await });
+    }
+
+    /// drains the queue into the relay for as long as this connection lasts,
+    /// the relay channel is bounded so awaiting each send paces it to the server's rate
+    async fn relay(&self, mut relay: mpsc::Sender<RelayFrame>, other_peer: Uuid) {
+        while !self.closed.get() {
+            let frame = self.outgoing.borrow_mut().next_frame(RELAY_CHUNK_SIZE);
+            let Some(frame) = frame else {
+                TimeoutFuture::new(RELAY_IDLE_MS).await;
+                continue;
+            };
+            let frame = RelayFrame {
+                peer: other_peer,
+                data: Uint8Array::new(&frame).to_vec(),
+            };
+            if relay.send(frame).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// restarts ICE when the network path breaks, e.g. after switching from
+    /// Wi-Fi to Ethernet, and escalates to rebuilding the connection and then
+    /// to relaying through the server when that doesn't help
+    fn set_on_ice_connection_state_change(&self, recovery: Recovery) {
+        let connection = self.clone();
+        let on_state_change: Box<dyn FnMut(Event)> = Box::new(move |_| {
+            let state = connection.peer_connection.ice_connection_state();
+            log!(format!("ice connection state: {:?}", state));
+            match state {
+                RtcIceConnectionState::Connected | RtcIceConnectionState::Completed => {
+                    connection.ice_restarts.set(0)
+                }
+                RtcIceConnectionState::Disconnected => {
+                    // disconnected often recovers on its own, give it some time
+                    let connection = connection.clone();
+                    let recovery = recovery.clone();
+                    Timeout::new(DISCONNECTED_TIMEOUT_MS, move || {
+                        let state = connection.peer_connection.ice_connection_state();
+                        if state == RtcIceConnectionState::Disconnected {
+                            connection.recover(&recovery);
+                        }
+                    })
+                    .forget();
+                }
+                RtcIceConnectionState::Failed => connection.recover(&recovery),
+                _ => {}
+            }
         });
-        let on_close = Closure::wrap(on_close);
-        self.data_channel
-            .set_onerror(Some(on_close.as_ref().unchecked_ref()));
-        on_close.forget();
+        let on_state_change = Closure::wrap(on_state_change);
+        self.peer_connection
+            .set_oniceconnectionstatechange(Some(on_state_change.as_ref().unchecked_ref()));
+        on_state_change.forget();
     }
 
-  
-    
-    fn create_data_channel(peer_connection: &RtcPeerConnection) -> RtcDataChannel {
-        let mut init = RtcDataChannelInit::new();
-        init.max_retransmits(16);
-        init.ordered(false);
-        let data_channel = peer_connection.create_data_channel_with_data_channel_dict("dc", &init);
-        data_channel
+    /// a failure with a working ICE path (e.g. DTLS) can't be fixed by an ICE restart
+    fn set_on_connection_state_change(&self, recovery: Recovery) {
+        let connection = self.clone();
+        let on_state_change: Box<dyn FnMut(Event)> = Box::new(move |_| {
+            let state = connection.peer_connection.connection_state();
+            log!(format!("peer connection state: {:?}", state));
+            connection.update_status(recovery.other_peer, state);
+            if state == RtcPeerConnectionState::Connected {
+                recovery.rebuilds.set(0);
+            }
+            if state == RtcPeerConnectionState::Failed
+                && connection.peer_connection.ice_connection_state() != RtcIceConnectionState::Failed
+            {
+                connection.ice_restarts.set(MAX_ICE_RESTARTS);
+                connection.recover(&recovery);
+            }
+        });
+        let on_state_change = Closure::wrap(on_state_change);
+        self.peer_connection
+            .set_onconnectionstatechange(Some(on_state_change.as_ref().unchecked_ref()));
+        on_state_change.forget();
     }
 
-    fn create_peer_connection() -> RtcPeerConnection {
-        let ice_servers = Array::new();
-        {
-            let server_entry = Object::new();
+    /// mirrors the connection state into the `ConnectionStates` store, once
+    /// connected the selected candidate pair tells a direct path from a TURN relay
+    fn update_status(&self, other_peer: Uuid, state: RtcPeerConnectionState) {
+        if self.closed.get() || self.relayed.get() {
+            return;
+        }
+        match state {
+            RtcPeerConnectionState::Connected => {
+                let peer_connection = self.peer_connection.clone();
+                spawn_local(async move {
+                    let status = Self::selected_candidate_pair_status(&peer_connection).await;
+                    Self::set_status(other_peer, status);
+                });
+            }
+            RtcPeerConnectionState::Failed => Self::set_status(other_peer, ConnectionStatus::Failed),
+            RtcPeerConnectionState::Closed => {}
+            _ => Self::set_status(other_peer, ConnectionStatus::Connecting),
+        }
+    }
 
-            Reflect::set(
-                &server_entry,
-                &"urls".into(),
-                &STUN_SERVER.to_owned().into(),
-            )
-            .expect("error creating stun entry");
+    fn set_status(other_peer: Uuid, status: ConnectionStatus) {
+        Dispatch::<ConnectionStates>::new().reduce_mut(|states| {
+            states.peers.insert(other_peer, status);
+        });
+    }
+
+    async fn selected_candidate_pair_status(peer_connection: &RtcPeerConnection) -> ConnectionStatus {
+        let relayed = stats::get_stats(peer_connection)
+            .await
+            .map(|report| ConnectionStats::from_report(&report, None).is_relayed())
+            .unwrap_or_default();
+        if relayed {
+            ConnectionStatus::Relayed
+        } else {
+            ConnectionStatus::Direct
+        }
+    }
+
+    fn recover(&self, recovery: &Recovery) {
+        if self.closed.get() || self.relayed.get() {
+            return;
+        }
+        if self.ice_restarts.get() < MAX_ICE_RESTARTS {
+            self.ice_restarts.set(self.ice_restarts.get() + 1);
+            log!("restarting ice, attempt", self.ice_restarts.get());
+            self.restart_ice(recovery.clone());
+        } else if recovery.rebuilds.get() < MAX_REBUILDS {
+            recovery.rebuilds.set(recovery.rebuilds.get() + 1);
+            log!("rebuilding the peer connection");
+            self.closed.set(true);
+            recovery
+                .tx
+                .send(CltMsg(ClientMessage::SignalingMessage(SignalingMessage::Reconnect(
+                    recovery.other_peer,
+                ))))
+                .expect("error sending reconnect");
+            recovery.on_reconnect.emit(());
+        } else {
+            // falls back to relaying through the server when no direct path was found
+            self.fall_back_to_relay(recovery);
+        }
+    }
+
+    /// a restart that can't even make its offer goes straight to rebuilding the connection
+    fn restart_ice(&self, recovery: Recovery) {
+        let connection = self.clone();
+        spawn_local(async move {
+            let mut options = RtcOfferOptions::new();
+            options.ice_restart(true);
+            connection.making_offer.set(true);
+            let offer = Self::create_offer_with_options(&connection.peer_connection, &options).await;
+            connection.making_offer.set(false);
+            let offer = match offer {
+                Ok(offer) => offer,
+                Err(error) => {
+                    log!("creating the ice restart offer failed", error);
+                    connection.ice_restarts.set(MAX_ICE_RESTARTS);
+                    connection.recover(&recovery);
+                    return;
+                }
+            };
+            let offer_msg = CltMsg(ClientMessage::SignalingMessage(SignalingMessage::Offer(
+                recovery.other_peer,
+                offer,
+            )));
+            recovery.tx.send(offer_msg).expect("error sending ice restart offer");
+        });
+    }
+
+    /// the other peer rebuilt its connection, this one has to follow
+    fn receive_reconnect(&self, tx: Sender<AppMessage>, other_peer: Uuid, on_reconnect: Callback<()>) {
+        let closed = self.closed.clone();
+        let mut rx = tx.subscribe();
+        spawn_local(async move {
+            while let Ok(msg) = rx.recv().await {
+                if closed.get() {
+                    break;
+                }
+                if let SrvrMsg(ServerMessage::SignalingMessage(SignalingMessage::Reconnect(
+                    signaler_peer,
+                ))) = msg
+                {
+                    if signaler_peer == other_peer {
+                        log!("the contacted peer is rebuilding the connection");
+                        closed.set(true);
+                        on_reconnect.emit(());
+                    }
+                }
+            }
+        });
+    }
+
+    fn set_on_error_callback(&self) {
+        for data_channel in self.data_channels.iter() {
+            let on_error: Box<dyn FnMut(JsValue)> = Box::new(move |data_channel_error| {
+                error!("data channel error: {:?}", data_channel_error);
+            });
+            let on_error = Closure::wrap(on_error);
+            data_channel.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            on_error.forget();
+        }
+    }
 
-            ice_servers.push(&server_entry);
+    fn set_on_close_callback(&self) {
+        for data_channel in self.data_channels.iter() {
+            let on_close: Box<dyn FnMut(JsValue)> = Box::new(move |_| {
+                log!("data channel closed");
+            });
+            let on_close = Closure::wrap(on_close);
+            data_channel.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+            on_close.forget();
         }
+    }
+
+    /// reliable but unordered, the chunk indices put the file back together
+    fn create_data_channels(peer_connection: &RtcPeerConnection) -> Vec<RtcDataChannel> {
+        let mut init = RtcDataChannelInit::new();
+        init.ordered(false);
+        (0..DATA_CHANNELS)
+            .map(|index| {
+                let data_channel = peer_connection
+                    .create_data_channel_with_data_channel_dict(&format!("dc{}", index), &init);
+                data_channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+                data_channel
+            })
+            .collect()
+    }
+
+    fn create_peer_connection() -> RtcPeerConnection {
+        let ice_servers: Array = Dispatch::<IceConfig>::new()
+            .get()
+            .ice_servers
+            .iter()
+            .map(Self::create_ice_server_entry)
+            .collect();
 
         let mut rtc_configuration = RtcConfiguration::new();
         rtc_configuration.ice_servers(&ice_servers);
@@ -352,4 +989,19 @@ impl WebRtcConnection {
         RtcPeerConnection::new_with_configuration(&rtc_configuration)
             .expect("error creating RtcPeerConnection")
     }
+
+    fn create_ice_server_entry(ice_server: &IceServer) -> Object {
+        let server_entry = Object::new();
+        let urls: Array = ice_server.urls.iter().map(JsValue::from).collect();
+        Reflect::set(&server_entry, &"urls".into(), &urls).expect("error creating ice server urls");
+        if let Some(username) = &ice_server.username {
+            Reflect::set(&server_entry, &"username".into(), &username.into())
+                .expect("error creating ice server username");
+        }
+        if let Some(credential) = &ice_server.credential {
+            Reflect::set(&server_entry, &"credential".into(), &credential.into())
+                .expect("error creating ice server credential");
+        }
+        server_entry
+    }
 }
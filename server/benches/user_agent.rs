@@ -0,0 +1,38 @@
+//! time spent on the user agent of every new websocket, run with `cargo bench --features bench`
+
+use std::time::Instant;
+
+use server::bench::UserAgents;
+
+const USER_AGENTS: [&str; 4] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:109.0) Gecko/20100101 Firefox/117.0",
+    "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/116.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 16_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.6 Mobile/15E148 Safari/604.1",
+];
+
+fn bench(name: &str, connections: u32, mut connect: impl FnMut(&str)) {
+    let start = Instant::now();
+    for connection in 0..connections {
+        connect(USER_AGENTS[connection as usize % USER_AGENTS.len()]);
+    }
+    let per_connection = start.elapsed() / connections;
+    println!("{:<32} {:>12?} per connection", name, per_connection);
+}
+
+fn main() {
+    // building the parser dominates, a few rounds are enough to see it
+    bench("parser built per connection", 10, |user_agent| {
+        UserAgents::uncached_parse(user_agent);
+    });
+
+    let start = Instant::now();
+    let user_agents = UserAgents::default();
+    println!("{:<32} {:>12?} once at startup", "shared parser", start.elapsed());
+    bench("shared parser, first visits", USER_AGENTS.len() as u32, |user_agent| {
+        user_agents.parse(user_agent);
+    });
+    bench("shared parser, cached", 100_000, |user_agent| {
+        user_agents.parse(user_agent);
+    });
+}
@@ -0,0 +1,90 @@
+//! compresses the client embedded by the `embed-client` feature, so the server
+//! starts with its `.br` and `.gz` variants instead of making them at startup
+
+fn main() {
+    #[cfg(feature = "embed-client")]
+    embed::compress("../dist");
+}
+
+#[cfg(feature = "embed-client")]
+mod embed {
+    use std::{
+        env, fs,
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use walkdir::WalkDir;
+
+    /// extensions of the files worth compressing, the others are media or archives already
+    const COMPRESSIBLE: [&str; 9] = ["html", "css", "js", "mjs", "wasm", "json", "svg", "txt", "map"];
+
+    /// writes the variants to `OUT_DIR` along with `compressed.rs`, which `assets` includes
+    pub fn compress(dist: &str) {
+        println!("cargo:rerun-if-changed={}", dist);
+        let out_dir = PathBuf::from(env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+        // the best quality takes most of a minute on an unoptimized build script,
+        // debug builds settle for a few percent bigger files
+        let quality = if env::var("PROFILE").as_deref() == Ok("release") { 11 } else { 9 };
+        let mut variants = String::new();
+        let files = WalkDir::new(dist)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file() && compressible(entry.path()));
+        for entry in files {
+            let path = entry.path();
+            let relative = path
+                .strip_prefix(dist)
+                .expect("walked out of dist")
+                .to_string_lossy()
+                .replace('\\', "/");
+            let data = fs::read(path).expect("unreadable client file");
+            for extension in ["br", "gz"] {
+                let variant = format!("{}.{}", relative, extension);
+                // left by the build, embedded as it is
+                if Path::new(dist).join(&variant).exists() {
+                    continue;
+                }
+                let compressed = match extension {
+                    "br" => brotli(&data, quality),
+                    _ => gzip(&data),
+                };
+                if compressed.len() >= data.len() {
+                    continue;
+                }
+                let file = out_dir.join("compressed").join(&variant);
+                fs::create_dir_all(file.parent().expect("variant without a directory"))
+                    .expect("unable to create the variant's directory");
+                fs::write(&file, compressed).expect("unable to write the variant");
+                variants.push_str(&format!("        {:?} => Some(include_bytes!({:?})),\n", variant, file));
+            }
+        }
+        let source = format!(
+            "/// the variants made by build.rs, by path and extension\n\
+             fn compressed(variant: &str) -> Option<&'static [u8]> {{\n    match variant {{\n{}        _ => None,\n    }}\n}}\n",
+            variants
+        );
+        fs::write(out_dir.join("compressed.rs"), source).expect("unable to write compressed.rs");
+    }
+
+    fn compressible(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| COMPRESSIBLE.contains(&extension))
+    }
+
+    fn brotli(data: &[u8], quality: u32) -> Vec<u8> {
+        let mut compressed = vec![];
+        let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, quality, 22);
+        writer.write_all(data).expect("writing to memory failed");
+        drop(writer);
+        compressed
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        encoder.write_all(data).expect("writing to memory failed");
+        encoder.finish().expect("writing to memory failed")
+    }
+}
@@ -0,0 +1,127 @@
+use std::{net::IpAddr, sync::Arc, time::UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    entities::{
+        messages::{AppMessage, CloseReason},
+        peer::Peer,
+        rooms::RoomId,
+    },
+    logging, AppState,
+};
+
+/// the dashboard asks for the token and calls the API with it
+const DASHBOARD: &str = include_str!("../admin.html");
+
+/// `/admin` and the API behind it, every API call needs `Authorization: Bearer <token>`
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let api = Router::new()
+        .route("/admin/api/rooms", get(list_rooms))
+        .route("/admin/api/rooms/close", post(close_room))
+        .route("/admin/api/peers/:id/disconnect", post(disconnect_peer))
+        .route_layer(middleware::from_fn_with_state(state, authorize));
+    Router::new().route("/admin", get(dashboard)).merge(api)
+}
+
+#[derive(Serialize)]
+struct RoomView {
+    id: RoomId,
+    peers: Vec<PeerView>,
+}
+
+#[derive(Serialize)]
+struct PeerView {
+    id: Uuid,
+    name: String,
+    os: String,
+    browser: String,
+    ip: IpAddr,
+    hidden: bool,
+    /// seconds since the unix epoch
+    connected_since: u64,
+}
+
+impl From<Peer> for PeerView {
+    fn from(peer: Peer) -> Self {
+        PeerView {
+            id: peer.id,
+            hidden: peer.is_hidden(),
+            name: peer.name,
+            os: peer.device.os,
+            browser: peer.device.browser,
+            ip: peer.ip,
+            connected_since: peer
+                .connected_since
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+async fn authorize<B>(State(state): State<Arc<AppState>>, request: Request<B>, next: Next<B>) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    let authorized = match (token, &state.admin_token) {
+        (Some(token), Some(expected)) => bool::from(token.as_bytes().ct_eq(expected.as_bytes())),
+        _ => false,
+    };
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
+
+async fn list_rooms(State(state): State<Arc<AppState>>) -> Json<Vec<RoomView>> {
+    let rooms = state.rooms.lock().await;
+    let rooms = rooms
+        .iter()
+        .map(|(id, room)| RoomView {
+            id: id.clone(),
+            peers: room.peers().into_iter().map(PeerView::from).collect(),
+        })
+        .collect();
+    Json(rooms)
+}
+
+async fn disconnect_peer(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> StatusCode {
+    let mut rooms = state.rooms.lock().await;
+    let Some(peer) = rooms.find_peer(&id).cloned() else {
+        return StatusCode::NOT_FOUND;
+    };
+    peer.kick(&mut rooms);
+    StatusCode::NO_CONTENT
+}
+
+/// everyone in the room is disconnected, they get a fresh room when they come back
+async fn close_room(State(state): State<Arc<AppState>>, Json(id): Json<RoomId>) -> StatusCode {
+    let mut rooms = state.rooms.lock().await;
+    let Some(room) = rooms.get(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    for peer in room.peers() {
+        let _ = peer.send(AppMessage::Close(CloseReason::RoomClosed));
+    }
+    rooms.delete_room(&id);
+    info!(room = %logging::redact(format!("{:?}", id)), "room closed");
+    StatusCode::NO_CONTENT
+}
@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+/// the Trunk build, embedded when the server is compiled with the `embed-client` feature
+#[derive(RustEmbed)]
+#[folder = "../dist"]
+struct Assets;
+
+/// encodings whose compressed variants are looked for, best first
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+include!(concat!(env!("OUT_DIR"), "/compressed.rs"));
+
+/// a variant left by the Trunk build, or made by build.rs from the file with `hash`
+fn variant(path: &str, hash: &[u8], extension: &str) -> Option<(Cow<'static, [u8]>, HeaderValue)> {
+    let variant = format!("{}.{}", path, extension);
+    if let Some(file) = Assets::get(&variant) {
+        return Some((file.data, etag(&file.metadata.sha256_hash(), "")));
+    }
+    // changes along with the file it was made from
+    compressed(&variant).map(|data| (Cow::Borrowed(data), etag(hash, extension)))
+}
+
+/// serves the embedded client on GET and HEAD, compressed when the browser accepts it
+pub async fn serve(uri: Uri, headers: HeaderMap) -> Response {
+    let mut path = uri.path().trim_start_matches('/').to_owned();
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    let Some(file) = Assets::get(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content_type = file.metadata.mimetype().to_owned();
+    let hash = file.metadata.sha256_hash();
+    let (data, etag, encoding) = ENCODINGS
+        .iter()
+        .filter(|(encoding, _)| accepts(&headers, encoding))
+        .find_map(|(encoding, extension)| {
+            variant(&path, &hash, extension).map(|(data, etag)| (data, etag, Some(*encoding)))
+        })
+        .unwrap_or_else(|| (file.data, etag(&hash, ""), None));
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|if_none_match| if_none_match.to_str().ok())
+        .is_some_and(|if_none_match| matches(if_none_match, &etag));
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        data.into_owned().into_response()
+    };
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&content_type).expect("invalid content type"),
+    );
+    response_headers.insert(header::ETAG, etag);
+    // revalidated every time, the etag keeps it to a 304 when nothing changed
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if let Some(encoding) = encoding {
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+    response
+}
+
+/// ignores the weights but for `q=0`, which refuses the encoding
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    let Some(accept_encoding) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|accept_encoding| accept_encoding.to_str().ok())
+    else {
+        return false;
+    };
+    accept_encoding.split(',').any(|accepted| {
+        let mut parts = accepted.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|param| param.replace(' ', "") == "q=0");
+        name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+/// `If-None-Match` is `*` or a list of tags, compared weakly as RFC 9110 asks for
+fn matches(if_none_match: &str, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    if_none_match.trim() == "*" || if_none_match.split(',').any(|tag| opaque_tag(tag) == opaque_tag(etag))
+}
+
+/// the quoted part of an entity tag, without the `W/` of a weak one
+fn opaque_tag(tag: &str) -> &str {
+    tag.trim().trim_start_matches("W/")
+}
+
+/// from the hash of the file and the extension of the variant, so each encoding gets its own
+fn etag(hash: &[u8], extension: &str) -> HeaderValue {
+    let hash: String = hash[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    let tag = if extension.is_empty() {
+        format!("\"{}\"", hash)
+    } else {
+        format!("\"{}-{}\"", hash, extension)
+    };
+    HeaderValue::from_str(&tag).expect("invalid etag")
+}
@@ -1,7 +1,7 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
-        ConnectInfo, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
     },
     http::HeaderMap,
     response::IntoResponse,
@@ -10,17 +10,25 @@ use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
 };
-use std::{net::SocketAddr, sync::Arc};
-use tokio::sync::broadcast::{channel, Sender};
+use std::{error::Error, net::SocketAddr, sync::Arc, time::Instant};
+use tokio::sync::{
+    broadcast::{channel, error::RecvError, Receiver, Sender},
+    mpsc,
+};
+use tracing::{debug, info_span, instrument, warn, Instrument};
 
 use crate::{
     entities::{
         messages::{
             AppMessage::{self, *},
-            ClientMessage,
+            ClientMessage, CloseReason, ConnectParams, RelayFrame,
         },
         peer::Peer,
     },
+    error::ServerError,
+    connections::ConnectionSlot,
+    logging,
+    throttle::Throttle,
     AppState,
 };
 
@@ -28,75 +36,271 @@ pub async fn socket_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
     ConnectInfo(socket_addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<ConnectParams>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |ws| connection_manager(ws, state, socket_addr, headers))
+    // tungstenite stops reading larger messages instead of buffering them for `listen` to check
+    let max_message_size = state.limits.max_message_size;
+    ws.max_message_size(max_message_size)
+        .max_frame_size(max_message_size)
+        .on_upgrade(move |ws| connection_manager(ws, state, socket_addr, params, headers))
 }
 
 pub async fn connection_manager(
-    ws: WebSocket,
+    mut ws: WebSocket,
     state: Arc<AppState>,
     socket_addr: SocketAddr,
+    params: ConnectParams,
     headers: HeaderMap,
 ) {
+    let span = info_span!("connection", addr = %logging::redact(socket_addr));
+    let Some(slot) = ConnectionSlot::take(state.clone(), socket_addr.ip()) else {
+        span.in_scope(|| warn!("too many connections from this address"));
+        let _ = ws.send(close_frame(CloseReason::TooManyConnections)).await;
+        return;
+    };
     let (sender, receiver) = ws.split();
     let (tx, _) = channel(100);
+    // relayed frames get their own bounded channels so a slow receiver
+    // pushes back on the sender instead of growing the buffers
+    let (incoming_frames_tx, incoming_frames_rx) = mpsc::channel(1);
+    let (outgoing_frames_tx, outgoing_frames_rx) = mpsc::channel(state.relay.buffer);
+    let throttle = Throttle::new(state.relay.rate);
+    let message_limit = Throttle::new(state.limits.message_rate);
+    state.metrics.connection_opened();
+    let opened = Instant::now();
 
-    let mut tasks = vec![];
+    let this_peer = span.in_scope(|| {
+        Peer::new(headers, socket_addr.ip(), params, &state.user_agents, tx.clone(), outgoing_frames_tx)
+    });
+    // subscribed before any task runs, so nothing sent while the others start is lost
+    let outgoing = tx.subscribe();
+    let incoming = tx.subscribe();
 
-    tasks.push(tokio::spawn(listen(receiver, tx.clone())));
-    tasks.push(tokio::spawn(send(sender, tx.clone())));
-    tasks.push(tokio::spawn(dispatch(socket_addr, headers, state, tx)));
+    let dispatching = tokio::spawn(
+        dispatch(this_peer, state.clone(), tx.clone(), incoming, incoming_frames_rx)
+            .instrument(span.clone()),
+    );
+    let listening = listen(
+        receiver,
+        tx.clone(),
+        incoming_frames_tx,
+        throttle,
+        message_limit,
+        state.clone(),
+    )
+    .instrument(span.clone());
+    let sending = send(sender, outgoing, outgoing_frames_rx, state.clone()).instrument(span.clone());
+    tokio::pin!(sending);
 
-    for task in tasks {
-        task.await.expect("error joining tasks");
+    // the websocket is done once the client stops talking or it can't be written to anymore,
+    // whichever comes first cancels the other
+    let listen_ended = tokio::select! {
+        _ = listening => true,
+        _ = &mut sending => false,
+    };
+    let _ = tx.send(SocketClosed);
+    if listen_ended {
+        // writes out what is left, like the close frame of a client going over a limit
+        sending.await;
+    }
+    if let Err(error) = dispatching.await {
+        span.in_scope(|| warn!(%error, "the peer task failed"));
     }
+    drop(slot);
+    state.metrics.connection_closed(opened.elapsed());
 }
 
+#[instrument(name = "peer", skip_all, fields(id = %this_peer.id))]
 async fn dispatch(
-    socket_addr: SocketAddr,
-    headers: HeaderMap,
+    mut this_peer: Peer,
     state: Arc<AppState>,
     tx: Sender<AppMessage>,
+    mut rx: Receiver<AppMessage>,
+    mut incoming_frames: mpsc::Receiver<RelayFrame>,
 ) {
-    let this_peer_ip = socket_addr.ip();
-    let this_peer = Peer::new(headers, this_peer_ip, tx.clone());
-    this_peer.init(state.clone()).await;
-    let mut rx = tx.subscribe();
-    while let Ok(message) = rx.recv().await {
-        if let CltMsg(message) = message {
-            match message {
-                ClientMessage::CheckOnline => this_peer.check_online(state.clone()),
-                ClientMessage::Disconnect => this_peer.disconnect(state.clone()).await,
-                ClientMessage::SignalingMessage(message) => {
-                    this_peer.signal(message, state.clone()).await
+    if let Err(reason) = this_peer.init(state.clone()).await {
+        close(&tx, reason);
+        return;
+    }
+    loop {
+        tokio::select! {
+            message = rx.recv() => match message {
+                Ok(CltMsg(message)) => {
+                    if let Err(error) = handle(&mut this_peer, message, state.clone()).await {
+                        reply_error(&tx, error);
+                    }
+                }
+                Ok(Close(_) | SocketClosed) => break,
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => warn!(skipped, "fell behind, messages were dropped"),
+                Err(RecvError::Closed) => {
+                    // listen and send would go on without anyone handling the messages
+                    close(&tx, CloseReason::Internal);
+                    break;
+                }
+            },
+            Some(frame) = incoming_frames.recv() => {
+                if let Err(error) = this_peer.relay(frame, state.clone()).await {
+                    reply_error(&tx, error);
                 }
             }
         }
     }
+    // a no-op when the client said goodbye or an admin took the peer out already
+    this_peer.disconnect(state).await;
 }
 
-async fn listen(mut receiver: SplitStream<WebSocket>, tx: Sender<AppMessage>) {
-    while let Some(Ok(Message::Text(message))) = receiver.next().await {
-        println!("from listner: {}", message);
-        let peer_message = serde_json::from_str::<ClientMessage>(&message)
-            .expect("client message is not a valid peer message");
-        tx.send(CltMsg(peer_message))
-            .expect("error sending data from listner");
+async fn handle(this_peer: &mut Peer, message: ClientMessage, state: Arc<AppState>) -> Result<(), ServerError> {
+    match message {
+        ClientMessage::CheckOnline => {
+            this_peer.check_online(state);
+            Ok(())
+        }
+        ClientMessage::Disconnect => {
+            this_peer.disconnect(state).await;
+            Ok(())
+        }
+        ClientMessage::SignalingMessage(message) => this_peer.signal(message, state).await,
+        ClientMessage::JoinRoom { room, passphrase } => {
+            this_peer.join_named_room(room, passphrase, state).await;
+            Ok(())
+        }
+        ClientMessage::SetHidden(hidden) => this_peer.set_hidden(hidden, state).await,
+        ClientMessage::Rename(name) => this_peer.rename(name, state).await,
+        ClientMessage::Block(id) => this_peer.block(id, state).await,
+        ClientMessage::Unblock(id) => this_peer.unblock(id, state).await,
     }
 }
 
-async fn send(mut sender: SplitSink<WebSocket, Message>, tx: Sender<AppMessage>) {
-    let mut rx = tx.subscribe();
-    while let Ok(message) = rx.recv().await {
-        if let SrvrMsg(message) = message {
-            let message = serde_json::to_string(&message).unwrap();
-            println!("from sender: {}", message);
-            sender
-                .send(Message::Text(message))
-                .await
-                .expect("error sending to server") // make loop continue
+/// tells the client what it got wrong, its connection stays open
+fn reply_error(tx: &Sender<AppMessage>, error: ServerError) {
+    debug!(%error, "rejected a message");
+    let _ = tx.send(SrvrMsg(error.to_message()));
+}
+
+async fn listen(
+    mut receiver: SplitStream<WebSocket>,
+    tx: Sender<AppMessage>,
+    frames: mpsc::Sender<RelayFrame>,
+    mut throttle: Throttle,
+    mut message_limit: Throttle,
+    state: Arc<AppState>,
+) {
+    while let Some(message) = receiver.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(error) if is_too_large(&error) => {
+                close(&tx, CloseReason::MessageTooLarge);
+                break;
+            }
+            Err(error) => {
+                state.metrics.websocket_error();
+                debug!(%error, "receiving failed");
+                break;
+            }
+        };
+        // relayed frames count too, a client could flood the server with tiny ones
+        if !message_limit.try_consume(1) {
+            close(&tx, CloseReason::RateLimited);
+            break;
+        }
+        match message {
+            Message::Text(message) => {
+                debug!(
+                    kind = %logging::message_kind(&message),
+                    payload = %logging::redact(&message),
+                    "received"
+                );
+                let peer_message = match serde_json::from_str::<ClientMessage>(&message) {
+                    Ok(peer_message) => peer_message,
+                    Err(error) => {
+                        reply_error(&tx, ServerError::InvalidMessage(error));
+                        continue;
+                    }
+                };
+                state.metrics.message_received(peer_message.kind());
+                if tx.send(CltMsg(peer_message)).is_err() {
+                    break;
+                }
+            }
+            Message::Binary(frame) => {
+                throttle.consume(frame.len()).await;
+                let Some(frame) = RelayFrame::decode(frame) else {
+                    reply_error(&tx, ServerError::InvalidFrame);
+                    continue;
+                };
+                if frames.send(frame).await.is_err() {
+                    break;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+}
+
+async fn send(
+    mut sender: SplitSink<WebSocket, Message>,
+    mut rx: Receiver<AppMessage>,
+    mut frames: mpsc::Receiver<RelayFrame>,
+    state: Arc<AppState>,
+) {
+    loop {
+        let message = tokio::select! {
+            message = rx.recv() => match message {
+                Ok(SrvrMsg(message)) => {
+                    let Ok(message) = serde_json::to_string(&message) else {
+                        continue;
+                    };
+                    debug!(
+                        kind = %logging::message_kind(&message),
+                        payload = %logging::redact(&message),
+                        "sent"
+                    );
+                    Message::Text(message)
+                }
+                Ok(Close(reason)) => {
+                    debug!(?reason, "closing");
+                    let _ = sender.send(close_frame(reason)).await;
+                    break;
+                }
+                Ok(SocketClosed) => break,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "fell behind, messages to the client were dropped");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Some(frame) = frames.recv() => Message::Binary(frame.encode()),
+        };
+        if let Err(error) = sender.send(message).await {
+            state.metrics.websocket_error();
+            debug!(%error, "sending failed");
+            break;
         }
     }
-    print!("rx dropped")
+    debug!("stopped sending, the connection is gone")
+}
+
+/// a message or a frame over `--max-message-size`
+fn is_too_large(error: &axum::Error) -> bool {
+    matches!(
+        error.source().and_then(|error| error.downcast_ref()),
+        Some(tungstenite::Error::Capacity(_))
+    )
+}
+
+/// has the send task close the websocket
+fn close(tx: &Sender<AppMessage>, reason: CloseReason) {
+    warn!(reason = reason.reason(), "closing the connection");
+    let _ = tx.send(Close(reason));
+}
+
+fn close_frame(reason: CloseReason) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: reason.code(),
+        reason: reason.reason().into(),
+    }))
 }
@@ -0,0 +1,40 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}};
+
+use crate::AppState;
+
+/// websockets open per ip address
+#[derive(Debug, Default)]
+pub struct Connections(Mutex<HashMap<IpAddr, usize>>);
+
+/// counts as an open connection of its ip until dropped
+pub struct ConnectionSlot {
+    state: Arc<AppState>,
+    ip: IpAddr,
+}
+
+impl ConnectionSlot {
+    /// `None` when the ip has `--max-connections-per-ip` connections open already
+    pub fn take(state: Arc<AppState>, ip: IpAddr) -> Option<Self> {
+        {
+            let mut connections = state.connections.0.lock().expect("poisoned connections");
+            let open = connections.entry(ip).or_default();
+            if *open >= state.limits.connections_per_ip {
+                return None;
+            }
+            *open += 1;
+        }
+        Some(ConnectionSlot { state, ip })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut connections = self.state.connections.0.lock().expect("poisoned connections");
+        if let Some(open) = connections.get_mut(&self.ip) {
+            *open -= 1;
+            if *open == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
@@ -2,7 +2,10 @@ pub mod peer;
 pub mod room;
 pub mod rooms;
 pub mod messages;
+pub mod passphrase;
 
 
 
 
+pub mod visibility;
+pub mod device;
@@ -0,0 +1,73 @@
+use axum::http::HeaderMap;
+use serde::Serialize;
+use tracing::debug;
+use crate::user_agent::UserAgents;
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum DeviceClass {
+    Phone,
+    Tablet,
+    #[default]
+    Desktop,
+}
+
+/// what the other peers show about a device
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceInfo {
+    pub os: String,
+    pub browser: String,
+    pub device_class: DeviceClass,
+    pub model: Option<String>,
+}
+
+impl DeviceInfo {
+    /// reads the user agent, refined by the client hints chromium sends along;
+    /// the model is a high entropy hint only the page can ask for, so it comes from the client
+    pub fn detect(user_agents: &UserAgents, headers: &HeaderMap, model_hint: Option<String>) -> Self {
+        let user_agent = header(headers, "user-agent").unwrap_or_default();
+        let parsed = user_agents.parse(&user_agent);
+        let os = header(headers, "sec-ch-ua-platform")
+            .map(|platform| platform.trim_matches('"').to_owned())
+            .filter(|platform| !platform.is_empty())
+            .or(parsed.os)
+            .unwrap_or("unknown".to_owned());
+        let browser = parsed.browser.unwrap_or("unknown".to_owned());
+        let model = model_hint
+            .or(parsed.model)
+            // chromium's reduced user agent hides the model behind a placeholder
+            .filter(|model| !model.is_empty() && model != "K" && model != "Other");
+        let device_class = Self::device_class(&user_agent, header(headers, "sec-ch-ua-mobile"));
+        debug!(%browser, %os, model = ?model, class = ?device_class, "device detected");
+        DeviceInfo {
+            os,
+            browser,
+            device_class,
+            model,
+        }
+    }
+
+    fn device_class(user_agent: &str, mobile_hint: Option<String>) -> DeviceClass {
+        if mobile_hint.as_deref() == Some("?1") {
+            return DeviceClass::Phone;
+        }
+        // android tablets leave "Mobile" out of their user agent
+        let is_android = user_agent.contains("Android");
+        if user_agent.contains("iPad")
+            || user_agent.contains("Tablet")
+            || (is_android && !user_agent.contains("Mobile"))
+        {
+            DeviceClass::Tablet
+        } else if user_agent.contains("Mobi") || user_agent.contains("iPhone") {
+            DeviceClass::Phone
+        } else {
+            DeviceClass::Desktop
+        }
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::peer::Peer;
+use crate::error::ErrorCode;
 
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -16,6 +17,7 @@ pub enum SignalingMessage {
     Offer(Uuid, String),
     Answer(Uuid, String),
     IceCandidate(Uuid, IceCandidate),
+    Reconnect(Uuid),
 }
 
 impl SignalingMessage {
@@ -24,29 +26,180 @@ impl SignalingMessage {
             SignalingMessage::Offer(other_peer, offer) => (other_peer,SignalingMessage::Offer(this_peer, offer)),
             SignalingMessage::Answer(other_peer, answer) => (other_peer,SignalingMessage::Answer(this_peer, answer)),
             SignalingMessage::IceCandidate(other_peer, ice_candidate) => (other_peer,SignalingMessage::IceCandidate(this_peer, ice_candidate)),
+            SignalingMessage::Reconnect(other_peer) => (other_peer,SignalingMessage::Reconnect(this_peer)),
         }
     }
 }
 
+/// a chunk of file data tunnelled through the websocket when the peers can't
+/// connect directly, sent as a binary frame: 16 bytes of peer id then the payload
+#[derive(Debug, Clone)]
+pub struct RelayFrame {
+    pub peer: Uuid,
+    pub data: Vec<u8>,
+}
+
+impl RelayFrame {
+    pub fn decode(mut frame: Vec<u8>) -> Option<Self> {
+        if frame.len() < 16 {
+            return None;
+        }
+        let data = frame.split_off(16);
+        let peer = Uuid::from_slice(&frame).ok()?;
+        Some(RelayFrame { peer, data })
+    }
+
+    pub fn encode(self) -> Vec<u8> {
+        let mut frame = self.peer.as_bytes().to_vec();
+        frame.extend(self.data);
+        frame
+    }
+
+    pub fn replace_other_id_with_this_peer_id(self, this_peer: Uuid) -> (Uuid, RelayFrame) {
+        (self.peer, RelayFrame { peer: this_peer, data: self.data })
+    }
+}
+
+/// an entry of the `RTCConfiguration.iceServers` list handed to the client
+#[derive(Serialize, Debug, Clone)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential: Option<String>,
+}
+
+/// why a peer couldn't join a named room
+#[derive(Serialize, Debug, Clone)]
+pub enum RoomError {
+    InvalidName,
+    PassphraseRequired,
+    WrongPassphrase,
+    RoomFull,
+    /// wrong passphrases were given too quickly
+    TooManyAttempts,
+}
+
+/// query of the websocket url, what the client remembers across connections
+#[derive(Deserialize, Debug)]
+pub struct ConnectParams {
+    /// joins without being announced, so a reload doesn't show a hidden peer
+    #[serde(default)]
+    pub hidden: bool,
+    /// display name picked by the user, a generated one is used without it
+    pub name: Option<String>,
+    /// stays the same for a browser, unlike the id of the connection
+    pub device: Option<Uuid>,
+    /// `navigator.userAgentData` model, chromium keeps it out of the headers
+    pub model: Option<String>,
+    /// the browser has a `DecompressionStream`, older ones get their files uncompressed
+    #[serde(default)]
+    pub decompress: bool,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub enum ClientMessage {
     CheckOnline,
     Disconnect,
     SignalingMessage(SignalingMessage),
+    /// moves the peer to a named room, the passphrase of a new room protects it
+    JoinRoom {
+        room: String,
+        passphrase: Option<String>,
+    },
+    /// a hidden peer is only shown to the peers it contacts
+    SetHidden(bool),
+    /// an empty name goes back to the generated one
+    Rename(String),
+    /// drops signaling and relayed data from that peer for the rest of the session
+    Block(Uuid),
+    Unblock(Uuid),
+}
+
+impl ClientMessage {
+    /// name of the message for the metrics, signaling messages by what they carry
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::CheckOnline => "CheckOnline",
+            ClientMessage::Disconnect => "Disconnect",
+            ClientMessage::SignalingMessage(SignalingMessage::Offer(..)) => "Offer",
+            ClientMessage::SignalingMessage(SignalingMessage::Answer(..)) => "Answer",
+            ClientMessage::SignalingMessage(SignalingMessage::IceCandidate(..)) => "IceCandidate",
+            ClientMessage::SignalingMessage(SignalingMessage::Reconnect(..)) => "Reconnect",
+            ClientMessage::JoinRoom { .. } => "JoinRoom",
+            ClientMessage::SetHidden(_) => "SetHidden",
+            ClientMessage::Rename(_) => "Rename",
+            ClientMessage::Block(_) => "Block",
+            ClientMessage::Unblock(_) => "Unblock",
+        }
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub enum ServerMessage {
-    PeerData(Peer),           
-    PeerJoined(Peer),          
+    PeerData(Box<Peer>),           
+    IceConfig(Vec<IceServer>),
+    PeerJoined(Box<Peer>),          
+    /// a hidden peer contacting this one
+    PeerRevealed(Box<Peer>),
     ConnectedPeers(Vec<Peer>), 
     PeerLeft(Uuid),           
+    /// a peer got a new display name
+    PeerUpdated(Box<Peer>),
+    RoomJoined(String),
+    RoomError(RoomError),
+    #[allow(dead_code)]
     CheckOnline,
     SignalingMessage(SignalingMessage),
+    /// the last message of this client couldn't be handled
+    Error { code: ErrorCode, message: String },
+}
+
+/// why the server closes a websocket, sent in its close frame
+#[derive(Debug, Clone, Copy)]
+pub enum CloseReason {
+    Kicked,
+    RoomClosed,
+    TooManyConnections,
+    RateLimited,
+    MessageTooLarge,
+    RoomFull,
+    /// the peer's task stopped without the websocket closing
+    Internal,
+}
+
+impl CloseReason {
+    /// in the 4000-4999 range left to applications
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseReason::Kicked => 4000,
+            CloseReason::RoomClosed => 4001,
+            CloseReason::TooManyConnections => 4002,
+            CloseReason::RateLimited => 4003,
+            CloseReason::MessageTooLarge => 4004,
+            CloseReason::RoomFull => 4005,
+            CloseReason::Internal => 4006,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            CloseReason::Kicked => "disconnected by an admin",
+            CloseReason::RoomClosed => "room closed by an admin",
+            CloseReason::TooManyConnections => "too many connections from this address",
+            CloseReason::RateLimited => "too many messages",
+            CloseReason::MessageTooLarge => "message too large",
+            CloseReason::RoomFull => "the room is full",
+            CloseReason::Internal => "the server dropped the connection",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum AppMessage {
     SrvrMsg(ServerMessage),
     CltMsg(ClientMessage),
+    /// closes the websocket of the connection
+    Close(CloseReason),
+    /// the websocket is gone, the peer leaves its room and the connection's tasks stop
+    SocketClosed,
 }
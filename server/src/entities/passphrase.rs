@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// OWASP's recommendation for PBKDF2-HMAC-SHA256, the hashing runs off the async workers
+/// and without the rooms locked
+const ITERATIONS: u32 = 600_000;
+/// wait after the first wrong passphrase, doubled by each one after it
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// salted PBKDF2-HMAC-SHA256 hash of a room passphrase, the passphrase itself is never kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Passphrase {
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl Passphrase {
+    /// slow on purpose, call it from a blocking task
+    pub fn new(passphrase: &str) -> Self {
+        let mut salt = [0; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Passphrase {
+            salt,
+            hash: hash(passphrase, &salt),
+        }
+    }
+
+    /// slow on purpose, call it from a blocking task
+    pub fn verify(&self, passphrase: &str) -> bool {
+        hash(passphrase, &self.salt).ct_eq(&self.hash).into()
+    }
+}
+
+/// the wrong passphrases a peer gave, it has to wait longer after each one
+#[derive(Debug, Clone, Default)]
+pub struct Attempts {
+    failed: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Attempts {
+    pub fn allowed(&self) -> bool {
+        self.retry_at.is_none_or(|retry_at| Instant::now() >= retry_at)
+    }
+
+    pub fn failed(&mut self) {
+        let backoff = FIRST_BACKOFF
+            .saturating_mul(1 << self.failed.min(16))
+            .min(MAX_BACKOFF);
+        self.failed += 1;
+        self.retry_at = Some(Instant::now() + backoff);
+    }
+
+    pub fn succeeded(&mut self) {
+        *self = Attempts::default();
+    }
+}
+
+fn hash(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), salt, ITERATIONS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_matches_the_reference() {
+        let expected = "d8d311abcf3421eec0939e39d3e58805cd3c714569a23a5e32a631f3f505a0d5";
+        let hash = hash("correct horse battery staple", b"shrut room salt!");
+        let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, expected);
+    }
+
+    #[test]
+    fn each_wrong_passphrase_waits_longer() {
+        let mut attempts = Attempts::default();
+        assert!(attempts.allowed());
+        attempts.failed();
+        assert!(!attempts.allowed());
+        let first = attempts.retry_at.unwrap();
+        attempts.failed();
+        assert!(attempts.retry_at.unwrap() > first);
+        attempts.succeeded();
+        assert!(attempts.allowed());
+    }
+
+    #[test]
+    fn only_the_same_passphrase_verifies() {
+        let passphrase = Passphrase::new("open sesame");
+        assert!(passphrase.verify("open sesame"));
+        assert!(!passphrase.verify("open sesame "));
+    }
+}
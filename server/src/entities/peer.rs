@@ -1,40 +1,96 @@
 use axum::http::HeaderMap;
 use rnglib::{Language, RNG};
 use serde::Serialize;
-use std::{net::IpAddr, sync::Arc};
-use tokio::sync::broadcast::{error::SendError, Sender};
-use user_agent_parser::UserAgentParser;
+use std::{net::IpAddr, sync::Arc, time::SystemTime};
+use tokio::{
+    sync::{
+        broadcast::{error::SendError, Sender},
+        mpsc,
+    },
+    task,
+};
+use tracing::{debug, info};
 use uuid::Uuid;
 
 use crate::{
     entities::messages::{AppMessage::*, ServerMessage},
+    error::ServerError,
+    user_agent::UserAgents,
     AppState,
 };
 
 use super::{
-    messages::{AppMessage, SignalingMessage},
+    device::DeviceInfo,
+    messages::{AppMessage, CloseReason, ConnectParams, RelayFrame, RoomError, SignalingMessage},
+    passphrase::{Attempts, Passphrase},
     room::Room,
+    rooms::{RoomId, Rooms},
+    visibility::Visibility,
 };
 
+/// longest name accepted for a named room
+const MAX_ROOM_NAME_LEN: usize = 64;
+/// display names are cut to this many characters
+const MAX_NAME_LEN: usize = 32;
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Peer {
     pub id: Uuid,
+    pub device_id: Uuid,
     pub name: String,
-    pub os: String,
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+    /// whether other peers may compress the files they send it
+    pub decompress: bool,
+    /// generated for the connection, used while the user hasn't picked a name
+    #[serde(skip_serializing)]
+    default_name: String,
+    #[serde(skip_serializing)]
+    pub room: RoomId,
+    /// only shown to the admins
     #[serde(skip_serializing)]
     pub ip: IpAddr,
     #[serde(skip_serializing)]
+    pub connected_since: SystemTime,
+    #[serde(skip_serializing)]
+    visibility: Visibility,
+    /// slows down guessing the passphrase of a named room
+    #[serde(skip_serializing)]
+    attempts: Attempts,
+    #[serde(skip_serializing)]
     tx: Sender<AppMessage>,
+    #[serde(skip_serializing)]
+    frames: mpsc::Sender<RelayFrame>,
 }
 
 impl Peer {
-    pub fn new(headers: HeaderMap, ip: IpAddr, tx: Sender<AppMessage>) -> Self {
+    pub fn new(
+        headers: HeaderMap,
+        ip: IpAddr,
+        params: ConnectParams,
+        user_agents: &UserAgents,
+        tx: Sender<AppMessage>,
+        frames: mpsc::Sender<RelayFrame>,
+    ) -> Self {
+        let default_name = Self::generate_name();
         Peer {
             id: Uuid::new_v4(),
-            name: Self::generate_name(),
-            os: Self::extract_peer_os(headers),
+            device_id: params.device.unwrap_or_else(Uuid::new_v4),
+            name: params
+                .name
+                .as_deref()
+                .and_then(Self::display_name)
+                .unwrap_or_else(|| default_name.clone()),
+            default_name,
+            device: DeviceInfo::detect(user_agents, &headers, params.model),
+            decompress: params.decompress,
+            room: RoomId::Network(ip),
             ip,
+            connected_since: SystemTime::now(),
+            visibility: Visibility::new(params.hidden),
+            attempts: Attempts::default(),
             tx,
+            frames,
         }
     }
     pub fn send(&self, message: AppMessage) -> Result<usize, SendError<AppMessage>> {
@@ -42,14 +98,28 @@ impl Peer {
         Ok(1)
     }
 
-    pub async fn init(&self, state: Arc<AppState>) {
+    /// a peer whose connection is closing misses the message, it leaves the room right after
+    fn notify(&self, message: AppMessage) {
+        if self.send(message).is_err() {
+            debug!(peer = %self.id, "connection closed, message dropped");
+        }
+    }
+
+    /// joins the room of the network, unless it is full
+    pub async fn init(&self, state: Arc<AppState>) -> Result<(), CloseReason> {
         let mut rooms = state.rooms.lock().await;
-        let room = rooms.get_or_create(&self.ip);
+        let room = rooms.get_or_create(&self.room);
+        if room.len() >= state.limits.max_room_size {
+            return Err(CloseReason::RoomFull);
+        }
         let other_peers = room.peers();
+        let visible_peers = room.peers_visible_to(&self.id);
         self.join_room(room);
         self.send_this_peer_data();
+        self.send_ice_config(&state);
         self.send_peer_joined_to_other_peers(&other_peers);
-        self.send_other_peers_data(other_peers);
+        self.send_other_peers_data(visible_peers);
+        Ok(())
     }
 
     pub fn join_room(&self, room: &mut Room) {
@@ -57,72 +127,266 @@ impl Peer {
     }
 
     fn send_this_peer_data(&self) {
-        let message = SrvrMsg(ServerMessage::PeerData(self.clone()));
-        self.send(message).expect("Error sending data");
+        let message = SrvrMsg(ServerMessage::PeerData(Box::new(self.clone())));
+        self.notify(message);
     }
 
-    fn send_peer_joined_to_other_peers(&self, other_peers: &Vec<Peer>) {
-        let message = SrvrMsg(ServerMessage::PeerJoined(self.clone()));
-        for peer in other_peers {
-            peer.send(message.clone())
-                .expect("error sending from send peer joined");
+    fn send_ice_config(&self, state: &AppState) {
+        let ice_servers = state.ice_config.ice_servers(self.id);
+        let message = SrvrMsg(ServerMessage::IceConfig(ice_servers));
+        self.notify(message);
+    }
+
+    fn send_peer_joined_to_other_peers(&self, other_peers: &[Peer]) {
+        let message = SrvrMsg(ServerMessage::PeerJoined(Box::new(self.clone())));
+        for peer in other_peers.iter().filter(|peer| self.is_visible_to(&peer.id)) {
+            peer.notify(message.clone());
         }
     }
 
     fn send_other_peers_data(&self, other_peers: Vec<Peer>) {
         let message = SrvrMsg(ServerMessage::ConnectedPeers(other_peers));
-        self.send(message)
-            .expect("error seding from send other peres data");
+        self.notify(message);
     }
 
-    pub fn check_online(&self, state: Arc<AppState>) {}
+    pub fn check_online(&self, _state: Arc<AppState>) {}
 
     pub async fn disconnect(&self, state: Arc<AppState>) {
-        println!("disconnect excuted");
         let mut rooms = state.rooms.lock().await;
-        {
-            let room = rooms.get_mut(&self.ip).unwrap();
-            room.remove_peer(&self.id);
-            room.peers().iter().for_each(|peer| {
-                peer.send(SrvrMsg(ServerMessage::PeerLeft(self.id)))
-                    .expect("error sending form disconnect");
-            });
+        if self.leave_room(&mut rooms) {
+            info!("disconnected");
         }
-        if rooms.get(&self.ip).unwrap().is_empty() {
-            rooms.delete_room(&self.ip)
+    }
+
+    /// removes the peer on behalf of an admin and closes its websocket
+    pub fn kick(&self, rooms: &mut Rooms) {
+        info!(id = %self.id, "kicked");
+        self.leave_room(rooms);
+        let _ = self.send(Close(CloseReason::Kicked));
+    }
+
+    /// false when the peer left already, or an admin took it out
+    fn leave_room(&self, rooms: &mut Rooms) -> bool {
+        let Some(room) = rooms.get_mut(&self.room) else {
+            return false;
+        };
+        if !room.remove_peer(&self.id) {
+            return false;
+        }
+        room.peers().iter().for_each(|peer| {
+            peer.notify(SrvrMsg(ServerMessage::PeerLeft(self.id)));
+        });
+        if room.is_empty() {
+            rooms.delete_room(&self.room)
+        }
+        true
+    }
+
+    /// leaves the current room for a named one, the peer creating the room
+    /// sets its passphrase and everyone joining later has to give it
+    pub async fn join_named_room(&mut self, name: String, passphrase: Option<String>, state: Arc<AppState>) {
+        let name = name.trim().to_owned();
+        let passphrase = passphrase.filter(|passphrase| !passphrase.is_empty());
+        if name.is_empty() || name.len() > MAX_ROOM_NAME_LEN {
+            self.notify(SrvrMsg(ServerMessage::RoomError(RoomError::InvalidName)));
+            return;
+        }
+        let room_id = RoomId::Named(name.clone());
+        if room_id == self.room {
+            return;
+        }
+        if !self.attempts.allowed() {
+            self.notify(SrvrMsg(ServerMessage::RoomError(RoomError::TooManyAttempts)));
+            return;
         }
+
+        let mut rooms = state.rooms.lock().await;
+        // the passphrase of a new room, or the one checked against, hashing happens on a
+        // blocking task without the rooms locked so wrong guesses don't hold up the server
+        let checked = loop {
+            let room = rooms.get(&room_id).filter(|room| !room.is_empty());
+            if room.is_some_and(|room| room.len() >= state.limits.max_room_size) {
+                self.notify(SrvrMsg(ServerMessage::RoomError(RoomError::RoomFull)));
+                return;
+            }
+            let expected = room.map(|room| room.passphrase().cloned());
+            let (known, passphrase) = match (expected.clone(), passphrase.clone()) {
+                (None, None) | (Some(None), _) => break None,
+                (Some(Some(_)), None) => {
+                    self.notify(SrvrMsg(ServerMessage::RoomError(RoomError::PassphraseRequired)));
+                    return;
+                }
+                (None, Some(passphrase)) => (None, passphrase),
+                (Some(Some(expected)), Some(passphrase)) => (Some(expected), passphrase),
+            };
+            drop(rooms);
+            let hashed = {
+                // a few hashes at a time, many peers guessing at once can't take all the threads
+                let _permit = state.hashing.acquire().await.expect("the hashing semaphore is never closed");
+                task::spawn_blocking(move || match known {
+                    None => Some(Passphrase::new(&passphrase)),
+                    Some(expected) => expected.verify(&passphrase).then_some(expected),
+                })
+                .await
+                .expect("passphrase hashing panicked")
+            };
+            let Some(hashed) = hashed else {
+                self.attempts.failed();
+                self.notify(SrvrMsg(ServerMessage::RoomError(RoomError::WrongPassphrase)));
+                return;
+            };
+            rooms = state.rooms.lock().await;
+            // the room may have been created, emptied or closed in the meantime
+            let current = rooms
+                .get(&room_id)
+                .filter(|room| !room.is_empty())
+                .map(|room| room.passphrase().cloned());
+            if current == expected {
+                break Some(hashed);
+            }
+        };
+        self.attempts.succeeded();
+        self.leave_room(&mut rooms);
+        self.room = room_id;
+        let room = rooms.get_or_create(&self.room);
+        if room.is_empty() {
+            room.set_passphrase(checked);
+        }
+        let other_peers = room.peers();
+        let visible_peers = room.peers_visible_to(&self.id);
+        self.join_room(room);
+        self.notify(SrvrMsg(ServerMessage::RoomJoined(name)));
+        self.send_peer_joined_to_other_peers(&other_peers);
+        self.send_other_peers_data(visible_peers);
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.visibility.is_hidden()
+    }
+
+    pub fn is_visible_to(&self, id: &Uuid) -> bool {
+        self.visibility.is_visible_to(id)
+    }
+
+    /// hiding tells everyone this peer left, showing it again announces it
+    /// to the peers it hadn't contacted
+    pub async fn set_hidden(&mut self, hidden: bool, state: Arc<AppState>) -> Result<(), ServerError> {
+        if self.visibility.is_hidden() == hidden {
+            return Ok(());
+        }
+        self.change_visibility(|visibility| visibility.set_hidden(hidden), state).await
+    }
+
+    /// applies `change` and tells the peers of the room that stopped or started seeing this one
+    async fn change_visibility(
+        &mut self,
+        change: impl FnOnce(&mut Visibility),
+        state: Arc<AppState>,
+    ) -> Result<(), ServerError> {
+        let mut rooms = state.rooms.lock().await;
+        let room = rooms.get_mut(&self.room).ok_or(ServerError::NoRoom)?;
+        let before = self.visibility.clone();
+        change(&mut self.visibility);
+        self.update_in(room)?;
+        for peer in room.peers().iter().filter(|peer| peer.id != self.id) {
+            match (before.is_visible_to(&peer.id), self.is_visible_to(&peer.id)) {
+                (true, false) => peer.notify(SrvrMsg(ServerMessage::PeerLeft(self.id))),
+                (false, true) => peer.notify(SrvrMsg(ServerMessage::PeerJoined(Box::new(self.clone())))),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// tells the peers that can see this one about the new name
+    pub async fn rename(&mut self, name: String, state: Arc<AppState>) -> Result<(), ServerError> {
+        self.name = Self::display_name(&name).unwrap_or_else(|| self.default_name.clone());
+        let mut rooms = state.rooms.lock().await;
+        let room = rooms.get_mut(&self.room).ok_or(ServerError::NoRoom)?;
+        self.update_in(room)?;
+        self.send_this_peer_data();
+        let message = SrvrMsg(ServerMessage::PeerUpdated(Box::new(self.clone())));
+        room.peers()
+            .iter()
+            .filter(|peer| peer.id != self.id && self.is_visible_to(&peer.id))
+            .for_each(|peer| peer.notify(message.clone()));
+        Ok(())
+    }
+
+    /// replaces the copy the room holds, which the other peers look at,
+    /// a peer that left after a `Disconnect` or a kick isn't put back
+    fn update_in(&self, room: &mut Room) -> Result<(), ServerError> {
+        if room.update_peer(self) {
+            Ok(())
+        } else {
+            Err(ServerError::UnknownPeer(self.id))
+        }
+    }
+
+    fn display_name(name: &str) -> Option<String> {
+        let name = name.trim();
+        (!name.is_empty()).then(|| name.chars().take(MAX_NAME_LEN).collect())
+    }
+
+    pub async fn block(&mut self, id: Uuid, state: Arc<AppState>) -> Result<(), ServerError> {
+        self.change_visibility(|visibility| visibility.block(id), state).await
+    }
+
+    pub async fn unblock(&mut self, id: Uuid, state: Arc<AppState>) -> Result<(), ServerError> {
+        self.change_visibility(|visibility| visibility.unblock(&id), state).await
     }
 
     fn generate_name() -> String {
-        let rng = RNG::try_from(&Language::Elven).unwrap();
+        let rng = RNG::from(&Language::Elven);
 
         let first_name = rng.generate_name();
         let last_name = rng.generate_name();
         format!("{} {}", first_name, last_name)
     }
-    pub fn extract_peer_os(headers: HeaderMap) -> String {
-        let ua_parser = UserAgentParser::from_path("regexes.yaml").unwrap();
-        if let Some(user_agent) = headers.get("user-agent") {
-            let os = ua_parser
-                .parse_os(user_agent.to_str().unwrap())
-                .name
-                .unwrap_or("unknown".into())
-                .to_string();
-            println!("{:?}", os);
-            os
-        } else {
-            "unknown".to_owned()
+    pub async fn signal(&mut self, message: SignalingMessage, state: Arc<AppState>) -> Result<(), ServerError> {
+        let (other_peer_id, message) = message.replace_other_id_with_this_peer_id(self.id);
+
+        let mut rooms = state.rooms.lock().await;
+        let room = rooms.get_mut(&self.room).ok_or(ServerError::NoRoom)?;
+        room.get(&self.id).ok_or(ServerError::UnknownPeer(self.id))?;
+        let other_peer = room
+            .get(&other_peer_id)
+            .ok_or(ServerError::UnknownPeer(other_peer_id))?
+            .clone();
+        if other_peer.visibility.has_blocked(&self.id) {
+            debug!(blocked_by = %other_peer_id, "dropping signaling from a blocked peer");
+            return Ok(());
+        }
+        // a hidden peer shows itself to the peers it contacts, before its offer
+        if self.visibility.reveal_to(other_peer_id) {
+            other_peer.notify(SrvrMsg(ServerMessage::PeerRevealed(Box::new(self.clone()))));
+            self.update_in(room)?;
         }
+        other_peer
+            .send(SrvrMsg(ServerMessage::SignalingMessage(message)))
+            .map_err(|_| ServerError::UnknownPeer(other_peer_id))?;
+        Ok(())
     }
 
-    pub async fn signal(&self, message: SignalingMessage, state: Arc<AppState>) {
-        let (other_peer_id, message) = message.replace_other_id_with_this_peer_id(self.id.clone());
+    pub async fn relay(&self, frame: RelayFrame, state: Arc<AppState>) -> Result<(), ServerError> {
+        let (other_peer_id, frame) = frame.replace_other_id_with_this_peer_id(self.id);
 
-        let rooms = state.rooms.lock().await;
-        let room = rooms.get(&self.ip).expect("no room fond");
-        let other_peer = room.get(&other_peer_id).expect("no peer with this id");
-        other_peer
-            .send(SrvrMsg(ServerMessage::SignalingMessage(message)))
-            .expect("error sending from signal");
+        let frames = {
+            let rooms = state.rooms.lock().await;
+            let room = rooms.get(&self.room).ok_or(ServerError::NoRoom)?;
+            room.get(&self.id).ok_or(ServerError::UnknownPeer(self.id))?;
+            let other_peer = room
+                .get(&other_peer_id)
+                .ok_or(ServerError::UnknownPeer(other_peer_id))?;
+            if other_peer.visibility.has_blocked(&self.id) {
+                return Ok(());
+            }
+            other_peer.frames.clone()
+        };
+        // waits while the other peer's buffer is full, slowing this peer down to its pace
+        frames
+            .send(frame)
+            .await
+            .map_err(|_| ServerError::UnknownPeer(other_peer_id))
     }
 }
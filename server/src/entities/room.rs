@@ -1,37 +1,66 @@
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use super::peer::Peer;
+use super::{passphrase::Passphrase, peer::Peer};
 
 #[derive(Debug, Default)]
 pub struct Room {
     peers: HashMap<Uuid, Peer>,
+    /// set by the peer that created the room, asked from everyone joining after
+    passphrase: Option<Passphrase>,
 }
 
 impl Room {
-    pub fn new() -> Self {
-        Room {
-            peers: HashMap::new(),
-        }
-    }
-
     pub fn get(&self, id: &Uuid) -> Option<&Peer> {
         self.peers.get(id)
     }
 
     pub fn receive_peer(&mut self, peer: Peer) {
-        self.peers.insert(peer.id.clone(), peer);
+        self.peers.insert(peer.id, peer);
     }
 
-    pub fn remove_peer(&mut self, id: &Uuid) {
-        self.peers.remove(id);
+    /// replaces the copy of a peer that is still in the room, false if it left
+    pub fn update_peer(&mut self, peer: &Peer) -> bool {
+        match self.peers.get_mut(&peer.id) {
+            Some(copy) => {
+                *copy = peer.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// whether the peer was in the room
+    pub fn remove_peer(&mut self, id: &Uuid) -> bool {
+        self.peers.remove(id).is_some()
     }
 
     pub fn peers(&self) -> Vec<Peer> {
         self.peers.values().cloned().collect()
     }
 
+    /// everyone but the hidden peers that haven't contacted `id`
+    pub fn peers_visible_to(&self, id: &Uuid) -> Vec<Peer> {
+        self.peers
+            .values()
+            .filter(|peer| peer.is_visible_to(id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.peers.is_empty()
     }
+
+    pub fn passphrase(&self) -> Option<&Passphrase> {
+        self.passphrase.as_ref()
+    }
+
+    pub fn set_passphrase(&mut self, passphrase: Option<Passphrase>) {
+        self.passphrase = passphrase;
+    }
 }
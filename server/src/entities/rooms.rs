@@ -1,12 +1,20 @@
 use std::{collections::HashMap, net::IpAddr};
 
-use super::room::Room;
-
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
+use super::{peer::Peer, room::Room};
 
+/// peers on the same network meet in the room of their public ip,
+/// named rooms gather peers from anywhere
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RoomId {
+    Network(IpAddr),
+    Named(String),
+}
 
 #[derive(Debug)]
-pub struct Rooms(HashMap<IpAddr,Room>);
+pub struct Rooms(HashMap<RoomId, Room>);
 
 
 impl Rooms {
@@ -14,19 +22,28 @@ impl Rooms {
         Rooms(HashMap::new())
     }
 
-    pub fn get(&self, ip: &IpAddr) -> Option<&Room> {
-        self.0.get(ip)
+    pub fn get(&self, id: &RoomId) -> Option<&Room> {
+        self.0.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &RoomId) -> Option<&mut Room> {
+        self.0.get_mut(id)
+    }
+
+    pub fn get_or_create(&mut self, id: &RoomId) -> &mut Room {
+        self.0.entry(id.clone()).or_default()
     }
 
-    pub fn get_mut(&mut self, ip: &IpAddr) -> Option<&mut Room> {
-        self.0.get_mut(ip)
+    pub fn iter(&self) -> impl Iterator<Item = (&RoomId, &Room)> {
+        self.0.iter()
     }
 
-    pub fn get_or_create(&mut self, ip: &IpAddr) -> &mut Room {
-        self.0.entry(ip.clone()).or_insert_with(|| Room::new())
+    /// the room copy of a peer, wherever it is
+    pub fn find_peer(&self, id: &Uuid) -> Option<&Peer> {
+        self.0.values().find_map(|room| room.get(id))
     }
 
-    pub fn delete_room(&mut self, ip: &IpAddr) {
-        self.0.remove(ip);
+    pub fn delete_room(&mut self, id: &RoomId) {
+        self.0.remove(id);
     }
 }
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// who a peer is shown to and who it doesn't want to hear from
+#[derive(Clone, Debug, Default)]
+pub struct Visibility {
+    /// left out of the announcements, only shown to the peers in `revealed_to`
+    hidden: bool,
+    revealed_to: HashSet<Uuid>,
+    /// peers whose signaling and relayed data are dropped, and who don't see this one
+    blocked: HashSet<Uuid>,
+}
+
+impl Visibility {
+    pub fn new(hidden: bool) -> Self {
+        Visibility {
+            hidden,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// blocked peers don't see this one, hidden or not
+    pub fn is_visible_to(&self, id: &Uuid) -> bool {
+        !self.blocked.contains(id) && (!self.hidden || self.revealed_to.contains(id))
+    }
+
+    /// returns whether `id` didn't see the peer before
+    pub fn reveal_to(&mut self, id: Uuid) -> bool {
+        self.hidden && !self.blocked.contains(&id) && self.revealed_to.insert(id)
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+        self.revealed_to.clear();
+    }
+
+    pub fn has_blocked(&self, id: &Uuid) -> bool {
+        self.blocked.contains(id)
+    }
+
+    pub fn block(&mut self, id: Uuid) {
+        self.blocked.insert(id);
+    }
+
+    pub fn unblock(&mut self, id: &Uuid) {
+        self.blocked.remove(id);
+    }
+}
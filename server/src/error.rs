@@ -0,0 +1,60 @@
+use std::fmt;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::entities::messages::ServerMessage;
+
+/// what a client did wrong, it gets a `ServerMessage::Error` and keeps its connection
+#[derive(Debug)]
+pub enum ServerError {
+    /// a text message that isn't a `ClientMessage`
+    InvalidMessage(serde_json::Error),
+    /// a binary message too short to hold the id of a peer
+    InvalidFrame,
+    /// signaling or relaying to a peer that isn't in the room, or is leaving it,
+    /// or messages of this peer after it left the room
+    UnknownPeer(Uuid),
+    /// the room of the peer has been closed
+    NoRoom,
+}
+
+/// sent along with the message so clients don't have to parse it
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidMessage,
+    InvalidFrame,
+    UnknownPeer,
+    NoRoom,
+}
+
+impl ServerError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServerError::InvalidMessage(_) => ErrorCode::InvalidMessage,
+            ServerError::InvalidFrame => ErrorCode::InvalidFrame,
+            ServerError::UnknownPeer(_) => ErrorCode::UnknownPeer,
+            ServerError::NoRoom => ErrorCode::NoRoom,
+        }
+    }
+
+    pub fn to_message(&self) -> ServerMessage {
+        ServerMessage::Error {
+            code: self.code(),
+            message: self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::InvalidMessage(error) => write!(f, "invalid message: {}", error),
+            ServerError::InvalidFrame => write!(f, "relayed frames start with the 16 bytes of a peer id"),
+            ServerError::UnknownPeer(id) => write!(f, "no peer {} in this room", id),
+            ServerError::NoRoom => write!(f, "the room has been closed"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
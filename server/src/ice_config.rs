@@ -0,0 +1,72 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use uuid::Uuid;
+
+use crate::{entities::messages::IceServer, turn_server::TurnConfig};
+
+/// the STUN/TURN servers handed to every peer for building its `RtcPeerConnection`
+#[derive(Debug, Clone)]
+pub struct IceConfig {
+    /// `stun:` and `turn:` urls configured by the operator
+    pub urls: Vec<String>,
+    /// shared secret of the configured TURN servers, when they use the TURN REST API
+    pub secret: Option<String>,
+    pub ttl: Duration,
+    pub relay: Option<TurnConfig>,
+}
+
+impl IceConfig {
+    pub fn ice_servers(&self, peer: Uuid) -> Vec<IceServer> {
+        let mut ice_servers: Vec<IceServer> = self
+            .urls
+            .iter()
+            .map(|url| match &self.secret {
+                Some(secret) if url.starts_with("turn") => {
+                    let (username, credential) = mint_credentials(secret, self.ttl, peer);
+                    IceServer {
+                        urls: vec![url.clone()],
+                        username: Some(username),
+                        credential: Some(credential),
+                    }
+                }
+                _ => IceServer {
+                    urls: vec![url.clone()],
+                    username: None,
+                    credential: None,
+                },
+            })
+            .collect();
+        if let Some(relay) = &self.relay {
+            let (username, credential) = mint_credentials(&relay.secret, self.ttl, peer);
+            ice_servers.push(IceServer {
+                urls: vec![relay.url()],
+                username: Some(username),
+                credential: Some(credential),
+            });
+        }
+        ice_servers
+    }
+}
+
+/// mints time-limited credentials following the TURN REST API scheme:
+/// the username is `<expiry timestamp>:<peer id>` and the password is
+/// `base64(hmac-sha1(secret, username))`, so the TURN server can check them
+/// without keeping any per peer state
+pub fn mint_credentials(secret: &str, ttl: Duration, peer: Uuid) -> (String, String) {
+    let expiry = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before unix epoch")
+        + ttl;
+    let username = format!("{}:{}", expiry.as_secs(), peer);
+    let credential = sign(secret, &username);
+    (username, credential)
+}
+
+pub fn sign(secret: &str, username: &str) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(username.as_bytes());
+    STANDARD.encode(mac.finalize().into_bytes())
+}
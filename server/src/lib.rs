@@ -1,56 +1,258 @@
-use std::{sync::Arc, net::{SocketAddr, IpAddr, Ipv6Addr}, str::FromStr};
+use std::{sync::Arc, num::NonZeroUsize, thread, net::{SocketAddr, IpAddr, Ipv6Addr, TcpListener}, str::FromStr, path::PathBuf, time::Duration};
 
 use axum::{Router, routing::get};
-use clap::Parser;
+use clap::{builder::RangedU64ValueParser, Parser};
 use entities::rooms::Rooms;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{info, Level};
+use tracing_subscriber::EnvFilter;
 use tower::ServiceBuilder;
+#[cfg(not(feature = "embed-client"))]
 use tower_http::services::ServeDir;
+use uuid::Uuid;
 
-use crate::connection_manager::socket_handler;
+use crate::{
+    connection_manager::socket_handler, connections::Connections, ice_config::IceConfig, metrics::Metrics,
+    turn_server::TurnConfig, user_agent::UserAgents,
+};
 
+mod admin;
+#[cfg(feature = "embed-client")]
+mod assets;
 mod entities;
 mod connection_manager;
+mod connections;
+mod error;
+mod ice_config;
+mod logging;
+mod metrics;
+mod throttle;
+mod tls;
+mod turn_server;
+mod user_agent;
+
+/// what `benches/` measures, not part of the server's interface
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::user_agent::UserAgents;
+}
 
 #[derive(Parser, Debug)]
 #[clap(name = "server", about = "A server for our wasm project!")]
-struct Opt {
-    /// set the log level
+pub struct Opt {
+    /// set the log level: error, warn, info, debug or trace, only trace
+    /// writes out SDP, ICE candidates and ip addresses
     #[clap(short = 'l', long = "log", default_value = "debug")]
-    log_level: String,
+    log_level: Level,
+    /// log one JSON object per line
+    #[clap(long = "log-json")]
+    log_json: bool,
     /// set the listen addr
     #[clap(short = 'a', long = "addr", default_value = "0.0.0.0")]
     addr: String,
     /// set the listen port
     #[clap(short = 'p', long = "port", default_value = "5050")]
     port: u16,
+    /// serve over https/wss using this PEM certificate chain
+    #[clap(long = "tls-cert", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the tls certificate
+    #[clap(long = "tls-key", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// reload the tls certificate when it changes, checking every N seconds
+    #[clap(long = "tls-reload", requires = "tls_cert", value_parser = clap::value_parser!(u64).range(1..))]
+    tls_reload: Option<u64>,
+    /// a stun: or turn: url handed to the clients, can be repeated
+    #[clap(long = "ice-server")]
+    ice_servers: Vec<String>,
+    /// TURN REST API shared secret of the turn: urls given with `--ice-server`
+    #[clap(long = "ice-secret")]
+    ice_secret: Option<String>,
+    /// run a TURN relay on this udp port for peers that can't connect directly
+    #[clap(long = "turn-port", requires = "turn_public_ip")]
+    turn_port: Option<u16>,
+    /// public ip address the TURN relay is reachable at
+    #[clap(long = "turn-public-ip", requires = "turn_port")]
+    turn_public_ip: Option<IpAddr>,
+    /// shared secret for minting TURN credentials, random if not set
+    #[clap(long = "turn-secret")]
+    turn_secret: Option<String>,
+    /// set the TURN realm
+    #[clap(long = "turn-realm", default_value = "shrut")]
+    turn_realm: String,
+    /// lifetime of the minted TURN credentials in seconds
+    #[clap(long = "turn-ttl", default_value = "86400")]
+    turn_ttl: u64,
+    /// bandwidth cap in bytes per second of a peer relaying through the websocket
+    #[clap(long = "relay-rate", default_value = "1048576", value_parser = clap::value_parser!(u64).range(1..))]
+    relay_rate: u64,
+    /// number of relayed frames buffered for a peer before the sender is slowed down
+    #[clap(long = "relay-buffer", default_value = "32", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    relay_buffer: usize,
+    /// websockets a single ip address can keep open, peers behind the same NAT share it
+    #[clap(long = "max-connections-per-ip", default_value = "128", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_connections_per_ip: usize,
+    /// messages per second a peer can send, bursts of one second worth are allowed
+    #[clap(long = "message-rate", default_value = "50", value_parser = clap::value_parser!(u64).range(1..))]
+    message_rate: u64,
+    /// largest websocket message accepted from a peer, in bytes
+    #[clap(long = "max-message-size", default_value = "65536", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_message_size: usize,
+    /// peers a room takes before turning new ones away
+    #[clap(long = "max-room-size", default_value = "128", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_room_size: usize,
+    /// expose Prometheus metrics on /metrics
+    #[clap(long = "metrics")]
+    metrics: bool,
+    /// enable the admin dashboard on /admin, its API takes `Authorization: Bearer <TOKEN>`
+    #[clap(long = "admin-token")]
+    admin_token: Option<String>,
+    /// serve /metrics and /admin on this address, over plain http, instead of the public one
+    #[clap(long = "admin-addr")]
+    admin_addr: Option<SocketAddr>,
+}
+
+/// limits of the websocket relay used when peers can't connect directly
+#[derive(Debug)]
+pub struct RelayConfig {
+    rate: u64,
+    buffer: usize,
+}
+
+/// what a client can do before its connection gets closed
+#[derive(Debug)]
+pub struct Limits {
+    connections_per_ip: usize,
+    message_rate: u64,
+    max_message_size: usize,
+    max_room_size: usize,
 }
 
 #[derive(Debug)]
 pub struct AppState {
     rooms: Mutex<Rooms>,
+    ice_config: IceConfig,
+    relay: RelayConfig,
+    limits: Limits,
+    connections: Connections,
+    user_agents: UserAgents,
+    metrics: Metrics,
+    admin_token: Option<String>,
+    /// room passphrases being hashed at once, one per core
+    hashing: Semaphore,
 }
 
 pub async fn run() {
     let opt = Opt::parse();
-    let state = Arc::new(AppState {
-        rooms: Mutex::new(Rooms::new()),
-    });
-
-    let app = Router::new()
-        .route("/ws", get(socket_handler))
-        .layer(ServiceBuilder::new())
-        .with_state(state)
-        .nest_service("/", ServeDir::new("../dist"));
-
+    init_logging(opt.log_level, opt.log_json);
     let addr = SocketAddr::from((
         IpAddr::from_str(opt.addr.as_str()).unwrap_or(IpAddr::V6(Ipv6Addr::LOCALHOST)),
         opt.port,
     ));
+    let listener = TcpListener::bind(addr).expect("Unable to bind the listen address");
+    serve(opt, listener).await
+}
+
+/// `level` applies to the server, the libraries below it only get to warn, unless `RUST_LOG` says otherwise
+fn init_logging(level: Level, json: bool) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,{}={}", env!("CARGO_CRATE_NAME"), level)));
+    let logger = tracing_subscriber::fmt().with_env_filter(filter);
+    if json {
+        logger.json().init();
+    } else {
+        logger.init();
+    }
+}
+
+/// serves on a listener bound by the caller, so tests can take any free port
+pub async fn serve(opt: Opt, listener: TcpListener) {
+    let relay = opt.turn_port.zip(opt.turn_public_ip).map(|(port, public_ip)| TurnConfig {
+        public_ip,
+        port,
+        realm: opt.turn_realm.clone(),
+        secret: opt.turn_secret.clone().unwrap_or_else(|| Uuid::new_v4().to_string()),
+    });
+    // keep the relay alive for as long as the server runs
+    let _turn_server = match &relay {
+        Some(relay) => Some(relay.start().await),
+        None => None,
+    };
+    let state = Arc::new(AppState {
+        rooms: Mutex::new(Rooms::new()),
+        ice_config: IceConfig {
+            urls: opt.ice_servers,
+            secret: opt.ice_secret,
+            ttl: Duration::from_secs(opt.turn_ttl),
+            relay,
+        },
+        relay: RelayConfig {
+            rate: opt.relay_rate,
+            buffer: opt.relay_buffer,
+        },
+        limits: Limits {
+            connections_per_ip: opt.max_connections_per_ip,
+            message_rate: opt.message_rate,
+            max_message_size: opt.max_message_size,
+            max_room_size: opt.max_room_size,
+        },
+        connections: Connections::default(),
+        user_agents: UserAgents::default(),
+        metrics: Metrics::default(),
+        admin_token: opt.admin_token,
+        hashing: Semaphore::new(thread::available_parallelism().map_or(1, NonZeroUsize::get)),
+    });
+
+    let mut admin = Router::new();
+    if opt.metrics {
+        admin = admin.route("/metrics", get(metrics::serve));
+    }
+    if state.admin_token.is_some() {
+        admin = admin.merge(admin::routes(state.clone()));
+    }
+    let mut app = Router::new().route("/ws", get(socket_handler));
+    match opt.admin_addr {
+        Some(admin_addr) => {
+            let admin = admin.with_state(state.clone());
+            info!("admin routes on http://{}", admin_addr);
+            tokio::spawn(async move {
+                axum::Server::bind(&admin_addr)
+                    .serve(admin.into_make_service())
+                    .await
+                    .expect("Unable to start admin server");
+            });
+        }
+        None => app = app.merge(admin),
+    }
+    let app = app.layer(ServiceBuilder::new()).with_state(state);
+    #[cfg(feature = "embed-client")]
+    let app = app.fallback(get(assets::serve));
+    #[cfg(not(feature = "embed-client"))]
+    let app = app.nest_service(
+        "/",
+        ServeDir::new("../dist").precompressed_br().precompressed_gzip(),
+    );
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
 
-    println!("listening on http://{}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await
-        .expect("Unable to start server");
+    let addr = listener.local_addr().expect("listener without an address");
+    listener.set_nonblocking(true).expect("Unable to set up the listener");
+    if let (Some(cert), Some(key)) = (opt.tls_cert, opt.tls_key) {
+        let config = tls::load_config(&cert, &key).await;
+        if let Some(period) = opt.tls_reload {
+            tls::watch_config(config.clone(), cert, key, Duration::from_secs(period));
+        }
+        info!("listening on https://{}", addr);
+        axum_server::from_tcp_rustls(listener, config)
+            .serve(app)
+            .await
+            .expect("Unable to start server");
+    } else {
+        info!("listening on http://{}", addr);
+        axum::Server::from_tcp(listener)
+            .expect("Unable to start server")
+            .serve(app)
+            .await
+            .expect("Unable to start server");
+    }
 }
@@ -0,0 +1,22 @@
+use std::fmt;
+
+use serde_json::Value;
+use tracing::Level;
+
+/// `value` at trace level, a placeholder otherwise, for the SDP, ICE candidates and ip addresses
+pub fn redact(value: impl fmt::Display) -> String {
+    if tracing::enabled!(Level::TRACE) {
+        value.to_string()
+    } else {
+        "<redacted>".to_owned()
+    }
+}
+
+/// name of the variant of a message serialized by serde, its payload stays out of the logs
+pub fn message_kind(json: &str) -> String {
+    match serde_json::from_str(json) {
+        Ok(Value::Object(message)) => message.keys().next().cloned().unwrap_or_default(),
+        Ok(Value::String(kind)) => kind,
+        _ => "unknown".to_owned(),
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{entities::rooms::Rooms, AppState};
+
+/// upper bounds of the room size buckets, in peers
+const ROOM_SIZE_BUCKETS: [f64; 7] = [1., 2., 3., 5., 10., 20., 50.];
+/// upper bounds of the connection duration buckets, from a second to a day
+const CONNECTION_DURATION_BUCKETS: [f64; 8] = [1., 10., 60., 300., 900., 3600., 14400., 86400.];
+
+/// counters of what went through the signaling server since it started,
+/// the peers and rooms are read from `Rooms` when scraped
+#[derive(Debug)]
+pub struct Metrics {
+    connections: AtomicU64,
+    websocket_errors: AtomicU64,
+    messages: Mutex<BTreeMap<&'static str, u64>>,
+    connection_durations: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            connections: AtomicU64::new(0),
+            websocket_errors: AtomicU64::new(0),
+            messages: Mutex::new(BTreeMap::new()),
+            connection_durations: Histogram::new(&CONNECTION_DURATION_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self, duration: Duration) {
+        self.connection_durations.observe(duration.as_secs_f64());
+    }
+
+    pub fn websocket_error(&self) {
+        self.websocket_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message_received(&self, kind: &'static str) {
+        *self.messages.lock().expect("poisoned metrics").entry(kind).or_default() += 1;
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self, rooms: &Rooms) -> String {
+        let room_sizes = Histogram::new(&ROOM_SIZE_BUCKETS);
+        let mut peers = 0;
+        for (_, room) in rooms.iter() {
+            room_sizes.observe(room.len() as f64);
+            peers += room.len();
+        }
+
+        let mut out = String::new();
+        write_metric(&mut out, "shrut_peers", "gauge", "peers connected right now");
+        let _ = writeln!(out, "shrut_peers {}", peers);
+        write_metric(&mut out, "shrut_rooms", "gauge", "rooms with at least one peer");
+        let _ = writeln!(out, "shrut_rooms {}", room_sizes.count());
+        write_metric(&mut out, "shrut_room_size", "histogram", "peers per room");
+        room_sizes.render(&mut out, "shrut_room_size");
+        write_metric(&mut out, "shrut_connections_total", "counter", "websocket connections opened");
+        let _ = writeln!(out, "shrut_connections_total {}", self.connections.load(Ordering::Relaxed));
+        write_metric(
+            &mut out,
+            "shrut_connection_duration_seconds",
+            "histogram",
+            "how long the closed websocket connections stayed open",
+        );
+        self.connection_durations.render(&mut out, "shrut_connection_duration_seconds");
+        write_metric(
+            &mut out,
+            "shrut_signaling_messages_total",
+            "counter",
+            "messages received from the clients by type",
+        );
+        for (kind, count) in self.messages.lock().expect("poisoned metrics").iter() {
+            let _ = writeln!(out, "shrut_signaling_messages_total{{type=\"{}\"}} {}", kind, count);
+        }
+        write_metric(
+            &mut out,
+            "shrut_websocket_errors_total",
+            "counter",
+            "websocket connections that failed to receive or send",
+        );
+        let _ = writeln!(out, "shrut_websocket_errors_total {}", self.websocket_errors.load(Ordering::Relaxed));
+        out
+    }
+}
+
+/// the `/metrics` route, enabled with `--metrics`
+pub async fn serve(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let metrics = state.metrics.render(&*state.rooms.lock().await);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics)
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// cumulative buckets like Prometheus expects them, the last one is `+Inf`
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    data: Mutex<HistogramData>,
+}
+
+#[derive(Debug)]
+struct HistogramData {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            data: Mutex::new(HistogramData {
+                buckets: vec![0; bounds.len()],
+                sum: 0.,
+                count: 0,
+            }),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let mut data = self.data.lock().expect("poisoned metrics");
+        for (bound, bucket) in self.bounds.iter().zip(data.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        data.sum += value;
+        data.count += 1;
+    }
+
+    fn count(&self) -> u64 {
+        self.data.lock().expect("poisoned metrics").count
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let data = self.data.lock().expect("poisoned metrics");
+        for (bound, bucket) in self.bounds.iter().zip(data.buckets.iter()) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, data.count);
+        let _ = writeln!(out, "{}_sum {}", name, data.sum);
+        let _ = writeln!(out, "{}_count {}", name, data.count);
+    }
+}
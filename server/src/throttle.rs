@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+
+/// token bucket limiting how many bytes per second a peer can push through the relay,
+/// or how many messages per second it can send
+#[derive(Debug)]
+pub struct Throttle {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Throttle {
+    /// `rate` is in bytes or messages per second and can't be 0, bursts of up to one second worth are allowed
+    pub fn new(rate: u64) -> Self {
+        let rate = rate as f64;
+        Throttle {
+            rate,
+            capacity: rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// waits until `amount` can be sent without exceeding the rate
+    pub async fn consume(&mut self, amount: usize) {
+        let amount = amount as f64;
+        self.refill();
+        if self.tokens < amount {
+            sleep(Duration::from_secs_f64((amount - self.tokens) / self.rate)).await;
+            self.refill();
+        }
+        self.tokens -= amount;
+    }
+
+    /// takes `amount` when the bucket holds enough, without waiting
+    pub fn try_consume(&mut self, amount: usize) -> bool {
+        let amount = amount as f64;
+        self.refill();
+        if self.tokens < amount {
+            return false;
+        }
+        self.tokens -= amount;
+        true
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
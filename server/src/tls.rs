@@ -0,0 +1,43 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::{fs, time::interval};
+use tracing::{info, warn};
+
+pub async fn load_config(cert: &Path, key: &Path) -> RustlsConfig {
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .expect("unable to load tls certificate or key")
+}
+
+/// reloads the certificate and key whenever one of the files changes on disk,
+/// so renewed certificates are picked up without restarting the server
+pub fn watch_config(config: RustlsConfig, cert: PathBuf, key: PathBuf, period: Duration) {
+    tokio::spawn(async move {
+        let mut last_modified = modified(&cert, &key).await;
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            let modified = modified(&cert, &key).await;
+            if modified == last_modified {
+                continue;
+            }
+            match config.reload_from_pem_file(&cert, &key).await {
+                Ok(()) => {
+                    info!("tls certificate reloaded");
+                    last_modified = modified;
+                }
+                // the files may be half written, keep the old config and retry on next tick
+                Err(err) => warn!(error = %err, "failed to reload tls certificate"),
+            }
+        }
+    });
+}
+
+async fn modified(cert: &Path, key: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path| async move { fs::metadata(path).await.and_then(|m| m.modified()).ok() };
+    (modified(cert).await, modified(key).await)
+}
@@ -0,0 +1,79 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::net::UdpSocket;
+use tracing::info;
+use turn::{
+    auth::{generate_auth_key, AuthHandler},
+    relay::relay_static::RelayAddressGeneratorStatic,
+    server::{
+        config::{ConnConfig, ServerConfig},
+        Server,
+    },
+    Error,
+};
+use webrtc_util::vnet::net::Net;
+
+use crate::ice_config::sign;
+
+/// settings of the TURN relay embedded in the server
+#[derive(Debug, Clone)]
+pub struct TurnConfig {
+    pub public_ip: IpAddr,
+    pub port: u16,
+    pub realm: String,
+    pub secret: String,
+}
+
+impl TurnConfig {
+    pub fn url(&self) -> String {
+        format!("turn:{}", SocketAddr::new(self.public_ip, self.port))
+    }
+
+    pub async fn start(&self) -> Server {
+        let conn = UdpSocket::bind(("0.0.0.0", self.port))
+            .await
+            .expect("unable to bind turn relay socket");
+        info!("turn relay listening on udp://{}", conn.local_addr().unwrap());
+
+        Server::new(ServerConfig {
+            conn_configs: vec![ConnConfig {
+                conn: Arc::new(conn),
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: self.public_ip,
+                    address: "0.0.0.0".to_owned(),
+                    net: Arc::new(Net::new(None)),
+                }),
+            }],
+            realm: self.realm.clone(),
+            auth_handler: Arc::new(RestApiAuthHandler {
+                secret: self.secret.clone(),
+            }),
+            channel_bind_timeout: Duration::from_secs(0),
+        })
+        .await
+        .expect("unable to start turn relay")
+    }
+}
+
+struct RestApiAuthHandler {
+    secret: String,
+}
+
+impl AuthHandler for RestApiAuthHandler {
+    fn auth_handle(&self, username: &str, realm: &str, _src_addr: SocketAddr) -> Result<Vec<u8>, Error> {
+        let expiry = username
+            .split_once(':')
+            .and_then(|(expiry, _)| expiry.parse::<u64>().ok())
+            .ok_or_else(|| Error::Other(format!("invalid turn username {}", username)))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if expiry < now {
+            return Err(Error::Other(format!("expired turn username {}", username)));
+        }
+        let password = sign(&self.secret, username);
+        Ok(generate_auth_key(username, realm, &password))
+    }
+}
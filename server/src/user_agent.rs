@@ -0,0 +1,63 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use user_agent_parser::UserAgentParser;
+
+/// regexes of the ua-parser project, built into the binary so the server starts from any directory
+const REGEXES: &str = include_str!("../regexes.yaml");
+/// distinct user agents remembered before the cache starts over
+const MAX_CACHED: usize = 1024;
+
+/// what the user agent string tells about a device
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParsedUserAgent {
+    pub os: Option<String>,
+    pub browser: Option<String>,
+    pub model: Option<String>,
+}
+
+/// parser built once at startup, matching a user agent runs through thousands
+/// of regexes so the results are kept, most visitors share a handful of browsers
+#[derive(Debug)]
+pub struct UserAgents {
+    parser: UserAgentParser,
+    cache: Mutex<HashMap<String, ParsedUserAgent>>,
+}
+
+impl Default for UserAgents {
+    fn default() -> Self {
+        UserAgents {
+            parser: UserAgentParser::from_str(REGEXES).expect("invalid user agent regexes"),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl UserAgents {
+    pub fn parse(&self, user_agent: &str) -> ParsedUserAgent {
+        if let Some(parsed) = self.cache.lock().expect("poisoned user agent cache").get(user_agent) {
+            return parsed.clone();
+        }
+        let parsed = parse(&self.parser, user_agent);
+        let mut cache = self.cache.lock().expect("poisoned user agent cache");
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert(user_agent.to_owned(), parsed.clone());
+        parsed
+    }
+
+    /// builds a parser the way every connection used to, for the benchmark
+    #[cfg(feature = "bench")]
+    pub fn uncached_parse(user_agent: &str) -> ParsedUserAgent {
+        let parser = UserAgentParser::from_str(REGEXES).expect("invalid user agent regexes");
+        parse(&parser, user_agent)
+    }
+}
+
+fn parse(parser: &UserAgentParser, user_agent: &str) -> ParsedUserAgent {
+    ParsedUserAgent {
+        os: parser.parse_os(user_agent).name.map(|name| name.to_string()),
+        browser: parser.parse_product(user_agent).name.map(|name| name.to_string()),
+        model: parser.parse_device(user_agent).model.map(|model| model.to_string()),
+    }
+}
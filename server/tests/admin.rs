@@ -0,0 +1,67 @@
+mod common;
+
+use common::{close_code, connect, peer_id, receive, request, send, start_server_with};
+use serde_json::json;
+
+const TOKEN: &str = "secret token";
+
+#[tokio::test]
+async fn the_api_wants_the_token() {
+    let addr = start_server_with(&["--admin-token", TOKEN]);
+
+    let (missing, _) = request(addr, "GET", "/admin/api/rooms", &[], "").await;
+    let (wrong, _) = request(addr, "GET", "/admin/api/rooms", &["Authorization: Bearer guess"], "").await;
+    let (right, _) = request(addr, "GET", "/admin/api/rooms", &[&format!("Authorization: Bearer {}", TOKEN)], "").await;
+
+    assert_eq!(missing, 401);
+    assert_eq!(wrong, 401);
+    assert_eq!(right, 200);
+}
+
+#[tokio::test]
+async fn the_api_is_off_without_a_token() {
+    let addr = start_server_with(&[]);
+
+    let (status, _) = request(addr, "GET", "/admin/api/rooms", &[], "").await;
+
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn disconnected_peers_are_closed_with_4000() {
+    let addr = start_server_with(&["--admin-token", TOKEN]);
+    let mut socket = connect(addr).await;
+    let id = peer_id(&mut socket).await;
+
+    let path = format!("/admin/api/peers/{}/disconnect", id);
+    let (unauthorized, _) = request(addr, "POST", &path, &[], "").await;
+    assert_eq!(unauthorized, 401);
+    let (status, _) = request(addr, "POST", &path, &[&format!("Authorization: Bearer {}", TOKEN)], "").await;
+
+    assert!((200..300).contains(&status), "disconnecting answered {}", status);
+    assert_eq!(close_code(&mut socket).await, 4000);
+}
+
+#[tokio::test]
+async fn peers_of_a_closed_room_are_closed_with_4001() {
+    let addr = start_server_with(&["--admin-token", TOKEN]);
+    let mut socket = connect(addr).await;
+    peer_id(&mut socket).await;
+    send(&mut socket, json!({ "JoinRoom": { "room": "lobby", "passphrase": null } })).await;
+    receive(&mut socket, "RoomJoined").await;
+
+    let (status, _) = request(
+        addr,
+        "POST",
+        "/admin/api/rooms/close",
+        &[
+            &format!("Authorization: Bearer {}", TOKEN),
+            "Content-Type: application/json",
+        ],
+        &json!({ "Named": "lobby" }).to_string(),
+    )
+    .await;
+
+    assert!((200..300).contains(&status), "closing answered {}", status);
+    assert_eq!(close_code(&mut socket).await, 4001);
+}
@@ -0,0 +1,72 @@
+//! needs a Trunk build in `../dist`, like the feature itself
+#![cfg(feature = "embed-client")]
+
+mod common;
+
+use common::{fetch, start_server};
+
+#[tokio::test]
+async fn files_go_out_in_an_encoding_the_browser_accepts() {
+    let addr = start_server();
+
+    let plain = fetch(addr, "GET", "/", &[], "").await;
+    let gzip = fetch(addr, "GET", "/", &["Accept-Encoding: gzip"], "").await;
+    let brotli = fetch(addr, "GET", "/", &["Accept-Encoding: gzip, br"], "").await;
+    let refused = fetch(addr, "GET", "/", &["Accept-Encoding: br;q=0, gzip"], "").await;
+
+    assert_eq!(plain.status, 200);
+    assert_eq!(plain.header("Content-Type"), Some("text/html"));
+    assert_eq!(plain.header("Content-Encoding"), None);
+    assert_eq!(gzip.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(brotli.header("Content-Encoding"), Some("br"));
+    assert_eq!(refused.header("Content-Encoding"), Some("gzip"));
+    assert!(gzip.body.len() < plain.body.len());
+    assert_eq!(gzip.header("Content-Type"), plain.header("Content-Type"));
+    assert_eq!(plain.header("Vary"), Some("accept-encoding"));
+}
+
+#[tokio::test]
+async fn each_encoding_has_its_own_etag() {
+    let addr = start_server();
+
+    let plain = fetch(addr, "GET", "/index.html", &[], "").await;
+    let again = fetch(addr, "GET", "/index.html", &[], "").await;
+    let gzip = fetch(addr, "GET", "/index.html", &["Accept-Encoding: gzip"], "").await;
+
+    let etag = plain.header("ETag").expect("no etag");
+    assert_eq!(again.header("ETag"), Some(etag));
+    assert_ne!(gzip.header("ETag"), Some(etag));
+}
+
+#[tokio::test]
+async fn unchanged_files_are_not_modified() {
+    let addr = start_server();
+    let etag = fetch(addr, "GET", "/", &[], "").await.header("ETag").unwrap().to_owned();
+
+    let same = fetch(addr, "GET", "/", &[&format!("If-None-Match: {}", etag)], "").await;
+    let listed = fetch(addr, "GET", "/", &[&format!("If-None-Match: \"other\", {}", etag)], "").await;
+    let weak = fetch(addr, "GET", "/", &[&format!("If-None-Match: W/{}", etag)], "").await;
+    let any = fetch(addr, "GET", "/", &["If-None-Match: *"], "").await;
+    let other = fetch(addr, "GET", "/", &["If-None-Match: \"other\""], "").await;
+
+    assert_eq!(same.status, 304);
+    assert!(same.body.is_empty());
+    assert_eq!(listed.status, 304);
+    assert_eq!(weak.status, 304);
+    assert_eq!(any.status, 304);
+    assert_eq!(other.status, 200);
+}
+
+#[tokio::test]
+async fn only_get_and_head_are_answered() {
+    let addr = start_server();
+
+    let head = fetch(addr, "HEAD", "/", &[], "").await;
+    let post = fetch(addr, "POST", "/", &[], "").await;
+    let missing = fetch(addr, "GET", "/missing.js", &[], "").await;
+
+    assert_eq!(head.status, 200);
+    assert!(head.body.is_empty());
+    assert_eq!(post.status, 405);
+    assert_eq!(missing.status, 404);
+}
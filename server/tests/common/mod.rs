@@ -0,0 +1,200 @@
+// every test binary builds this module and uses only some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::Duration,
+};
+
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use server::{serve, Opt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+pub type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// how long a test waits for a message before failing
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a server with the default options on a free port of localhost
+pub fn start_server() -> SocketAddr {
+    start_server_with(&[])
+}
+
+/// `args` as given on the command line
+pub fn start_server_with(args: &[&str]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("no free port");
+    let addr = listener.local_addr().expect("listener without an address");
+    let opt = Opt::parse_from(["server"].iter().chain(args));
+    tokio::spawn(serve(opt, listener));
+    addr
+}
+
+/// a port of localhost free when asked, for the addresses the server binds itself like `--admin-addr`
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .expect("no free port")
+        .local_addr()
+        .expect("listener without an address")
+}
+
+/// status and body of a plain http request, waits for a server that is still starting
+pub async fn request(addr: SocketAddr, method: &str, path: &str, headers: &[&str], body: &str) -> (u16, String) {
+    let response = fetch(addr, method, path, headers, body).await;
+    (response.status, String::from_utf8_lossy(&response.body).into_owned())
+}
+
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// the whole response, for the tests that look at its headers
+pub async fn fetch(addr: SocketAddr, method: &str, path: &str, headers: &[&str], body: &str) -> Response {
+    let requesting = async {
+        let mut stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => sleep(Duration::from_millis(20)).await,
+            }
+        };
+        let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, addr);
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+        stream.write_all(request.as_bytes()).await.expect("error sending the request");
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.expect("error reading the response");
+        response
+    };
+    let response = timeout(TIMEOUT, requesting).await.expect("no response from the server");
+    let head_len = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("response without a head");
+    let head = String::from_utf8_lossy(&response[..head_len]).into_owned();
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .expect("response without a status");
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_owned(), value.trim().to_owned()))
+        .collect();
+    Response {
+        status,
+        headers,
+        body: response[head_len + 4..].to_vec(),
+    }
+}
+
+pub async fn connect(addr: SocketAddr) -> Socket {
+    let (socket, _) = connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("error connecting to the server");
+    socket
+}
+
+pub async fn send(socket: &mut Socket, message: Value) {
+    socket
+        .send(Message::Text(message.to_string()))
+        .await
+        .expect("error sending to the server");
+}
+
+/// the content of the next `kind` message, the others are skipped
+pub async fn receive(socket: &mut Socket, kind: &str) -> Value {
+    let receiving = async {
+        loop {
+            let message = socket
+                .next()
+                .await
+                .expect("the server closed the connection")
+                .expect("error receiving from the server");
+            if let Message::Text(text) = message {
+                let message: Value = serde_json::from_str(&text).expect("server message is not json");
+                if let Some(content) = message.get(kind) {
+                    return content.clone();
+                }
+            }
+        }
+    };
+    timeout(TIMEOUT, receiving)
+        .await
+        .unwrap_or_else(|_| panic!("no {} message from the server", kind))
+}
+
+/// the next relayed frame, the id of the peer it came from followed by its data
+pub async fn receive_frame(socket: &mut Socket) -> (String, Vec<u8>) {
+    let receiving = async {
+        loop {
+            let message = socket
+                .next()
+                .await
+                .expect("the server closed the connection")
+                .expect("error receiving from the server");
+            if let Message::Binary(mut frame) = message {
+                let data = frame.split_off(16);
+                let id = Uuid::from_slice(&frame).expect("frame without a peer id");
+                return (id.to_string(), data);
+            }
+        }
+    };
+    timeout(TIMEOUT, receiving).await.expect("no frame from the server")
+}
+
+/// relays `data` to the peer with `id`
+pub async fn send_frame(socket: &mut Socket, id: &str, data: &[u8]) {
+    let mut frame = Uuid::parse_str(id).expect("not a peer id").as_bytes().to_vec();
+    frame.extend(data);
+    socket
+        .send(Message::Binary(frame))
+        .await
+        .expect("error sending to the server");
+}
+
+/// the code of the close frame the server ends the connection with
+pub async fn close_code(socket: &mut Socket) -> u16 {
+    let receiving = async {
+        loop {
+            let message = socket
+                .next()
+                .await
+                .expect("the server dropped the connection without closing it")
+                .expect("error receiving from the server");
+            if let Message::Close(frame) = message {
+                return frame.map(|frame| frame.code.into()).unwrap_or_default();
+            }
+        }
+    };
+    timeout(TIMEOUT, receiving).await.expect("the server kept the connection open")
+}
+
+/// the id the server gave this connection
+pub async fn peer_id(socket: &mut Socket) -> String {
+    receive(socket, "PeerData").await["id"]
+        .as_str()
+        .expect("peer without an id")
+        .to_owned()
+}
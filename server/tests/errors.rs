@@ -0,0 +1,117 @@
+mod common;
+
+use common::{connect, peer_id, receive, send, start_server, Socket};
+use futures_util::SinkExt;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+/// the server answered the last message with an error but still handles the next ones
+async fn assert_still_connected(socket: &mut Socket) {
+    send(socket, json!({ "Rename": "still here" })).await;
+    assert_eq!(receive(socket, "PeerData").await["name"], "still here");
+}
+
+async fn receive_error(socket: &mut Socket) -> Value {
+    receive(socket, "Error").await
+}
+
+#[tokio::test]
+async fn invalid_json_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+
+    socket.send(Message::Text("{not json".into())).await.unwrap();
+
+    assert_eq!(receive_error(&mut socket).await["code"], "InvalidMessage");
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn unknown_message_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+
+    send(&mut socket, json!({ "Shout": "hello" })).await;
+
+    let error = receive_error(&mut socket).await;
+    assert_eq!(error["code"], "InvalidMessage");
+    assert!(error["message"].as_str().unwrap().contains("Shout"));
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn message_with_wrong_fields_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+
+    send(&mut socket, json!({ "SignalingMessage": { "Offer": "not an id" } })).await;
+
+    assert_eq!(receive_error(&mut socket).await["code"], "InvalidMessage");
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn signal_to_unknown_peer_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+    let unknown = Uuid::new_v4();
+
+    send(&mut socket, json!({ "SignalingMessage": { "Offer": [unknown, "v=0"] } })).await;
+
+    let error = receive_error(&mut socket).await;
+    assert_eq!(error["code"], "UnknownPeer");
+    assert!(error["message"].as_str().unwrap().contains(&unknown.to_string()));
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn signal_to_peer_of_another_room_is_rejected() {
+    let addr = start_server();
+    let mut socket = connect(addr).await;
+    peer_id(&mut socket).await;
+    let mut other = connect(addr).await;
+    let other_id = peer_id(&mut other).await;
+    send(&mut other, json!({ "JoinRoom": { "room": "elsewhere", "passphrase": null } })).await;
+    receive(&mut other, "RoomJoined").await;
+
+    send(&mut socket, json!({ "SignalingMessage": { "Reconnect": other_id } })).await;
+
+    assert_eq!(receive_error(&mut socket).await["code"], "UnknownPeer");
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn relay_to_unknown_peer_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+    let mut frame = Uuid::new_v4().as_bytes().to_vec();
+    frame.extend(b"some file data");
+
+    socket.send(Message::Binary(frame)).await.unwrap();
+
+    assert_eq!(receive_error(&mut socket).await["code"], "UnknownPeer");
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn relay_frame_without_peer_id_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+
+    socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+
+    assert_eq!(receive_error(&mut socket).await["code"], "InvalidFrame");
+    assert_still_connected(&mut socket).await;
+}
+
+#[tokio::test]
+async fn empty_room_name_is_rejected() {
+    let mut socket = connect(start_server()).await;
+    peer_id(&mut socket).await;
+
+    send(&mut socket, json!({ "JoinRoom": { "room": "  ", "passphrase": null } })).await;
+
+    assert_eq!(receive(&mut socket, "RoomError").await, "InvalidName");
+    assert_still_connected(&mut socket).await;
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use clap::Parser;
+use common::{close_code, connect, peer_id, receive, send, send_frame, start_server_with};
+use futures_util::SinkExt;
+use serde_json::json;
+use server::Opt;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn connections_over_the_limit_are_closed() {
+    let addr = start_server_with(&["--max-connections-per-ip", "2"]);
+    let mut first = connect(addr).await;
+    peer_id(&mut first).await;
+    let mut second = connect(addr).await;
+    peer_id(&mut second).await;
+
+    let mut third = connect(addr).await;
+
+    assert_eq!(close_code(&mut third).await, 4002);
+}
+
+#[tokio::test]
+async fn peers_sending_too_fast_are_closed() {
+    let mut socket = connect(start_server_with(&["--message-rate", "2"])).await;
+    peer_id(&mut socket).await;
+
+    for _ in 0..5 {
+        send(&mut socket, json!("CheckOnline")).await;
+    }
+
+    assert_eq!(close_code(&mut socket).await, 4003);
+}
+
+#[tokio::test]
+async fn relayed_frames_count_against_the_message_rate() {
+    let mut socket = connect(start_server_with(&["--message-rate", "2"])).await;
+    let id = peer_id(&mut socket).await;
+
+    for _ in 0..5 {
+        send_frame(&mut socket, &id, &[0; 16]).await;
+    }
+
+    assert_eq!(close_code(&mut socket).await, 4003);
+}
+
+#[tokio::test]
+async fn text_messages_over_the_size_limit_are_closed() {
+    let mut socket = connect(start_server_with(&["--max-message-size", "1024"])).await;
+    peer_id(&mut socket).await;
+
+    socket.send(Message::Text("x".repeat(2048))).await.unwrap();
+
+    assert_eq!(close_code(&mut socket).await, 4004);
+}
+
+#[tokio::test]
+async fn frames_over_the_size_limit_are_closed() {
+    let mut socket = connect(start_server_with(&["--max-message-size", "1024"])).await;
+    let id = peer_id(&mut socket).await;
+
+    send_frame(&mut socket, &id, &[0; 2048]).await;
+
+    assert_eq!(close_code(&mut socket).await, 4004);
+}
+
+#[tokio::test]
+async fn peers_joining_a_full_room_are_closed() {
+    let addr = start_server_with(&["--max-room-size", "1"]);
+    let mut first = connect(addr).await;
+    peer_id(&mut first).await;
+
+    let mut second = connect(addr).await;
+
+    assert_eq!(close_code(&mut second).await, 4005);
+}
+
+#[tokio::test]
+async fn full_named_rooms_turn_peers_away() {
+    let addr = start_server_with(&["--max-room-size", "1"]);
+    let mut first = connect(addr).await;
+    peer_id(&mut first).await;
+    send(&mut first, json!({ "JoinRoom": { "room": "full", "passphrase": null } })).await;
+    receive(&mut first, "RoomJoined").await;
+    let mut second = connect(addr).await;
+    peer_id(&mut second).await;
+
+    send(&mut second, json!({ "JoinRoom": { "room": "full", "passphrase": null } })).await;
+
+    assert_eq!(receive(&mut second, "RoomError").await, "RoomFull");
+}
+
+#[test]
+fn limits_of_zero_are_rejected() {
+    for flag in [
+        "--max-connections-per-ip",
+        "--message-rate",
+        "--max-message-size",
+        "--max-room-size",
+        "--relay-rate",
+        "--relay-buffer",
+    ] {
+        assert!(Opt::try_parse_from(["server", flag, "0"]).is_err(), "{} accepted 0", flag);
+        assert!(Opt::try_parse_from(["server", flag, "1"]).is_ok(), "{} refused 1", flag);
+    }
+}
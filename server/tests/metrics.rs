@@ -0,0 +1,78 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use common::{connect, free_addr, peer_id, request, send, start_server_with};
+use serde_json::json;
+use tokio::time::{sleep, timeout};
+
+/// the metrics once they contain `line`, the server counts after replying to the client
+async fn metrics_with(addr: SocketAddr, line: &str) -> String {
+    let polling = async {
+        loop {
+            let (status, body) = request(addr, "GET", "/metrics", &[], "").await;
+            assert_eq!(status, 200);
+            if body.lines().any(|metric| metric == line) {
+                return body;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), polling)
+        .await
+        .unwrap_or_else(|_| panic!("no `{}` in the metrics", line))
+}
+
+#[tokio::test]
+async fn connections_and_messages_are_counted() {
+    let addr = start_server_with(&["--metrics"]);
+    let mut socket = connect(addr).await;
+    peer_id(&mut socket).await;
+
+    send(&mut socket, json!("CheckOnline")).await;
+    send(&mut socket, json!("CheckOnline")).await;
+
+    let metrics = metrics_with(addr, "shrut_signaling_messages_total{type=\"CheckOnline\"} 2").await;
+    assert!(metrics.lines().any(|line| line == "shrut_connections_total 1"));
+    assert!(metrics.lines().any(|line| line == "shrut_peers 1"));
+    assert!(metrics.lines().any(|line| line == "shrut_room_size_bucket{le=\"1\"} 1"));
+}
+
+#[tokio::test]
+async fn closed_connections_are_timed() {
+    let addr = start_server_with(&["--metrics"]);
+    let mut socket = connect(addr).await;
+    peer_id(&mut socket).await;
+
+    socket.close(None).await.unwrap();
+
+    let metrics = metrics_with(addr, "shrut_connection_duration_seconds_count 1").await;
+    assert!(metrics
+        .lines()
+        .any(|line| line == "shrut_connection_duration_seconds_bucket{le=\"1\"} 1"));
+    assert!(metrics
+        .lines()
+        .any(|line| line == "shrut_connection_duration_seconds_bucket{le=\"+Inf\"} 1"));
+    assert!(metrics.lines().any(|line| line == "shrut_peers 0"));
+}
+
+#[tokio::test]
+async fn metrics_are_off_by_default() {
+    let addr = start_server_with(&[]);
+
+    let (status, _) = request(addr, "GET", "/metrics", &[], "").await;
+
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn admin_addr_takes_the_metrics_off_the_public_port() {
+    let admin_addr = free_addr();
+    let addr = start_server_with(&["--metrics", "--admin-addr", &admin_addr.to_string()]);
+    let mut socket = connect(addr).await;
+    peer_id(&mut socket).await;
+
+    let (status, _) = request(addr, "GET", "/metrics", &[], "").await;
+    assert_eq!(status, 404);
+    metrics_with(admin_addr, "shrut_connections_total 1").await;
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{connect, peer_id, receive, start_server, start_server_with};
+use serde_json::Value;
+
+#[tokio::test]
+async fn dropped_socket_leaves_the_room() {
+    let addr = start_server();
+    let mut staying = connect(addr).await;
+    peer_id(&mut staying).await;
+    let mut leaving = connect(addr).await;
+    let leaving_id = peer_id(&mut leaving).await;
+    receive(&mut staying, "PeerJoined").await;
+
+    // no `Disconnect` and no close frame, like a mobile browser killing the tab
+    drop(leaving);
+
+    assert_eq!(receive(&mut staying, "PeerLeft").await, leaving_id.as_str());
+}
+
+#[tokio::test]
+async fn closed_socket_leaves_the_room() {
+    let addr = start_server();
+    let mut staying = connect(addr).await;
+    peer_id(&mut staying).await;
+    let mut leaving = connect(addr).await;
+    let leaving_id = peer_id(&mut leaving).await;
+    receive(&mut staying, "PeerJoined").await;
+
+    leaving.close(None).await.unwrap();
+
+    assert_eq!(receive(&mut staying, "PeerLeft").await, leaving_id.as_str());
+}
+
+#[tokio::test]
+async fn peers_joining_later_only_see_connected_peers() {
+    let addr = start_server();
+    let mut staying = connect(addr).await;
+    let staying_id = peer_id(&mut staying).await;
+    let mut leaving = connect(addr).await;
+    peer_id(&mut leaving).await;
+    receive(&mut staying, "PeerJoined").await;
+    drop(leaving);
+    receive(&mut staying, "PeerLeft").await;
+
+    let mut joining = connect(addr).await;
+    let connected = receive(&mut joining, "ConnectedPeers").await;
+
+    let ids: Vec<&Value> = connected.as_array().unwrap().iter().map(|peer| &peer["id"]).collect();
+    assert_eq!(ids, [staying_id.as_str()]);
+}
+
+#[tokio::test]
+async fn closed_connection_frees_its_slot() {
+    let addr = start_server_with(&["--max-connections-per-ip", "1"]);
+    let mut first = connect(addr).await;
+    peer_id(&mut first).await;
+    drop(first);
+
+    let mut second = connect(addr).await;
+
+    // turned away with a close frame instead if the first connection were still counted
+    peer_id(&mut second).await;
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{connect, peer_id, receive, receive_frame, send, send_frame, start_server};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn hidden_peer_leaves_and_comes_back() {
+    let addr = start_server();
+    let mut watching = connect(addr).await;
+    peer_id(&mut watching).await;
+    let mut hiding = connect(addr).await;
+    let hiding_id = peer_id(&mut hiding).await;
+    receive(&mut watching, "PeerJoined").await;
+
+    send(&mut hiding, json!({ "SetHidden": true })).await;
+    assert_eq!(receive(&mut watching, "PeerLeft").await, hiding_id.as_str());
+
+    send(&mut hiding, json!({ "SetHidden": false })).await;
+    assert_eq!(receive(&mut watching, "PeerJoined").await["id"], hiding_id.as_str());
+}
+
+#[tokio::test]
+async fn hidden_peer_is_revealed_to_the_peer_it_signals() {
+    let addr = start_server();
+    let mut watching = connect(addr).await;
+    let watching_id = peer_id(&mut watching).await;
+    let mut hiding = connect(addr).await;
+    let hiding_id = peer_id(&mut hiding).await;
+    send(&mut hiding, json!({ "SetHidden": true })).await;
+    receive(&mut watching, "PeerLeft").await;
+
+    send(&mut hiding, json!({ "SignalingMessage": { "Offer": [watching_id, "v=0"] } })).await;
+
+    assert_eq!(receive(&mut watching, "PeerRevealed").await["id"], hiding_id.as_str());
+    assert_eq!(receive(&mut watching, "SignalingMessage").await["Offer"][0], hiding_id.as_str());
+}
+
+#[tokio::test]
+async fn rename_is_shown_to_the_other_peers() {
+    let addr = start_server();
+    let mut watching = connect(addr).await;
+    peer_id(&mut watching).await;
+    let mut renaming = connect(addr).await;
+    let renaming_id = peer_id(&mut renaming).await;
+    receive(&mut watching, "PeerJoined").await;
+
+    send(&mut renaming, json!({ "Rename": "  laptop  " })).await;
+
+    assert_eq!(receive(&mut renaming, "PeerData").await["name"], "laptop");
+    let updated = receive(&mut watching, "PeerUpdated").await;
+    assert_eq!(updated["id"], renaming_id.as_str());
+    assert_eq!(updated["name"], "laptop");
+}
+
+#[tokio::test]
+async fn disconnected_peer_stays_out_of_the_room() {
+    let addr = start_server();
+    let mut staying = connect(addr).await;
+    let staying_id = peer_id(&mut staying).await;
+    let mut leaving = connect(addr).await;
+    peer_id(&mut leaving).await;
+    receive(&mut staying, "PeerJoined").await;
+    send(&mut leaving, json!("Disconnect")).await;
+    receive(&mut staying, "PeerLeft").await;
+
+    send(&mut leaving, json!({ "Rename": "ghost" })).await;
+    assert_eq!(receive(&mut leaving, "Error").await["code"], "UnknownPeer");
+    send(&mut leaving, json!({ "SetHidden": true })).await;
+    assert_eq!(receive(&mut leaving, "Error").await["code"], "UnknownPeer");
+
+    let mut joining = connect(addr).await;
+    let connected = receive(&mut joining, "ConnectedPeers").await;
+    let ids: Vec<&Value> = connected.as_array().unwrap().iter().map(|peer| &peer["id"]).collect();
+    assert_eq!(ids, [staying_id.as_str()]);
+}
+
+#[tokio::test]
+async fn blocked_peer_no_longer_sees_the_peer() {
+    let addr = start_server();
+    let mut blocking = connect(addr).await;
+    let blocking_id = peer_id(&mut blocking).await;
+    let mut blocked = connect(addr).await;
+    let blocked_id = peer_id(&mut blocked).await;
+
+    send(&mut blocking, json!({ "Block": blocked_id })).await;
+    assert_eq!(receive(&mut blocked, "PeerLeft").await, blocking_id.as_str());
+
+    send(&mut blocking, json!({ "Unblock": blocked_id })).await;
+    assert_eq!(receive(&mut blocked, "PeerJoined").await["id"], blocking_id.as_str());
+}
+
+#[tokio::test]
+async fn offers_and_frames_of_a_blocked_peer_are_dropped() {
+    let addr = start_server();
+    let mut blocking = connect(addr).await;
+    let blocking_id = peer_id(&mut blocking).await;
+    let mut blocked = connect(addr).await;
+    let blocked_id = peer_id(&mut blocked).await;
+    let mut other = connect(addr).await;
+    let other_id = peer_id(&mut other).await;
+    send(&mut blocking, json!({ "Block": blocked_id })).await;
+    receive(&mut blocked, "PeerLeft").await;
+
+    send(&mut blocked, json!({ "SignalingMessage": { "Offer": [blocking_id, "blocked"] } })).await;
+    send_frame(&mut blocked, &blocking_id, b"blocked").await;
+    // handled in order, once these arrive the ones before were dropped
+    send(&mut blocked, json!({ "SignalingMessage": { "Offer": [other_id, "v=0"] } })).await;
+    receive(&mut other, "SignalingMessage").await;
+    send_frame(&mut blocked, &other_id, b"data").await;
+    receive_frame(&mut other).await;
+
+    send(&mut blocking, json!({ "Unblock": blocked_id })).await;
+    receive(&mut blocked, "PeerJoined").await;
+    send(&mut blocked, json!({ "SignalingMessage": { "Offer": [blocking_id, "unblocked"] } })).await;
+    send_frame(&mut blocked, &blocking_id, b"unblocked").await;
+
+    assert_eq!(receive(&mut blocking, "SignalingMessage").await["Offer"][1], "unblocked");
+    assert_eq!(receive_frame(&mut blocking).await, (blocked_id, b"unblocked".to_vec()));
+}